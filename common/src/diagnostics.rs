@@ -0,0 +1,338 @@
+use std::io;
+use ariadne::{Cache, Color, Config, IndexType, Label as ALabel, Report, ReportKind, Span as ASpan};
+use serde_json::{json, Value};
+use crate::error_codes::ErrorCode;
+use crate::source_file::{Id, SourceFile};
+use crate::span::Span;
+use crate::warnings::Warning;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+// A machine-applicable suggestion: replace the text under `span` (empty for an insertion).
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct FixIt {
+    pub span: Span,
+    pub replacement: String,
+}
+
+impl FixIt {
+    pub fn insert(at: Span, text: impl Into<String>) -> FixIt {
+        FixIt { span: at, replacement: text.into() }
+    }
+
+    pub fn describe(&self) -> String {
+        if self.span.is_empty() {
+            format!("insert '{}' here", self.replacement)
+        } else if self.replacement.is_empty() {
+            "remove this".to_string()
+        } else {
+            format!("replace with '{}'", self.replacement)
+        }
+    }
+}
+
+// Applies the fixes in source order; a fix overlapping one already applied is skipped.
+// Insertions at the same offset are all kept, in the order given.
+pub fn apply_fixes(text: &str, fixes: &[FixIt]) -> String {
+    let mut fixes: Vec<&FixIt> = fixes.iter().collect();
+    fixes.sort_by_key(|fix| fix.span.start());
+
+    let mut result = String::with_capacity(text.len());
+    let mut position = 0;
+
+    for fix in fixes {
+        let range = fix.span.range();
+
+        if range.start < position || range.end > text.len() {
+            continue;
+        }
+
+        result.push_str(&text[position..range.start]);
+        result.push_str(&fix.replacement);
+        position = range.end;
+    }
+
+    result.push_str(&text[position..]);
+    result
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<ErrorCode>,
+    pub warning: Option<Warning>,
+    pub message: String,
+    pub span: Span,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Vec<String>,
+    pub fixes: Vec<FixIt>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, span: Span, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            severity,
+            code: None,
+            warning: None,
+            message: message.into(),
+            span,
+            labels: Vec::new(),
+            notes: Vec::new(),
+            help: Vec::new(),
+            fixes: Vec::new(),
+        }
+    }
+
+    pub fn error(span: Span, message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Severity::Error, span, message)
+    }
+
+    pub fn warning(warning: Warning, span: Span, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            warning: Some(warning),
+            ..Diagnostic::new(Severity::Warning, span, message)
+        }
+    }
+
+    pub fn with_code(mut self, code: ErrorCode) -> Diagnostic {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Diagnostic {
+        self.labels.push(Label { span, message: message.into() });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Diagnostic {
+        self.help.push(help.into());
+        self
+    }
+
+    pub fn with_fix(mut self, fix: FixIt) -> Diagnostic {
+        self.fixes.push(fix);
+        self
+    }
+
+    // Moves every span through `map`, e.g. from preprocessed text back to the original files.
+    // Like Clang, fix-its that end up in a macro expansion are dropped: applying them at the
+    // macro's use would edit the wrong text.
+    pub fn map_spans(mut self, map: impl Fn(&Span) -> Span) -> Diagnostic {
+        self.span = map(&self.span);
+
+        for label in &mut self.labels {
+            label.span = map(&label.span);
+        }
+
+        self.fixes.retain_mut(|fix| {
+            fix.span = map(&fix.span);
+            fix.span.expansions().is_empty()
+        });
+
+        self
+    }
+
+    pub fn report(&self) -> Report<'static, Span> {
+        let kind = match self.severity {
+            Severity::Error => ReportKind::Error,
+            Severity::Warning => ReportKind::Warning,
+            Severity::Note => ReportKind::Advice,
+        };
+
+        // Name the flag that controls a warning, the way GCC and Clang do.
+        let message = match self.warning {
+            Some(warning) if self.severity == Severity::Error => format!("{} [-Werror={}]", self.message, warning.name()),
+            Some(warning) => format!("{} [{}]", self.message, warning),
+            None => self.message.clone(),
+        };
+
+        // spans are byte offsets; ariadne still shows columns in characters
+        let mut builder = Report::build(kind, self.span.clone())
+            .with_config(Config::default().with_index_type(IndexType::Byte))
+            .with_message(message)
+            .with_labels(self.labels.iter().map(|label| ALabel::new(label.span.clone())
+                .with_message(&label.message)
+                .with_color(Color::Primary)))
+            .with_labels(self.fixes.iter().map(|fix| ALabel::new(fix.span.clone())
+                .with_message(format!("help: {}", fix.describe()))
+                .with_color(Color::Green)))
+            // for code from macros, show each macro down to where the code is written, as Clang does
+            .with_labels(self.span.expansions().iter().map(|expansion| ALabel::new(expansion.definition.clone())
+                .with_message(format!("note: expanded from macro '{}'", expansion.name))
+                .with_color(Color::Cyan)));
+
+        if let Some(code) = self.code {
+            builder = builder.with_code(code);
+        }
+
+        builder.with_notes(&self.notes);
+        builder.with_helps(&self.help);
+
+        builder.finish()
+    }
+
+    pub fn eprint<C: Cache<Id>>(&self, cache: C) -> io::Result<()> {
+        self.report().eprint(cache)
+    }
+
+    // Line and column are 1-based; a span at the end of the file is just after its last character.
+    pub fn to_json(&self, file: &SourceFile) -> Value {
+        let range = self.span.range();
+        let (line, column) = file.line_pos_or_end(range.start);
+
+        json!({
+            "file": file.filename,
+            "byte_start": range.start,
+            "byte_end": range.end,
+            "line": line,
+            "column": column,
+            "severity": self.severity.as_str(),
+            "code": self.code.map(|code| code.code()),
+            "warning": self.warning.map(|warning| warning.name()),
+            "message": self.message,
+            "fixes": self.fixes.iter().map(|fix| json!({
+                "byte_start": fix.span.start(),
+                "byte_end": fix.span.end(),
+                "replacement": fix.replacement,
+            })).collect::<Vec<_>>(),
+            "expansions": self.span.expansions().iter().map(|expansion| json!({
+                "macro": expansion.name,
+                "file": match expansion.definition.source() {
+                    Id::Unit(name) => name.clone(),
+                    Id::Main => file.filename.clone(),
+                },
+                "byte_start": expansion.definition.start(),
+                "byte_end": expansion.definition.end(),
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::span::Expansion;
+    use super::*;
+
+    #[test]
+    fn builder_collects_parts() {
+        let diagnostic = Diagnostic::error(Span::new_with_unit(2, 3, Id::Main), "Unknown token.")
+            .with_code(ErrorCode::UnknownToken)
+            .with_label(Span::new_with_unit(2, 3, Id::Main), "Unknown token")
+            .with_note("a note")
+            .with_help("some help");
+
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(diagnostic.code, Some(ErrorCode::UnknownToken));
+        assert_eq!(diagnostic.labels.len(), 1);
+        assert_eq!(diagnostic.notes, vec!["a note".to_string()]);
+        assert_eq!(diagnostic.help, vec!["some help".to_string()]);
+    }
+
+    #[test]
+    fn map_spans_moves_labels_and_fixes() {
+        let diagnostic = Diagnostic::error(Span::new_with_unit(2, 3, Id::Main), "Unknown token.")
+            .with_label(Span::new_with_unit(2, 3, Id::Main), "Unknown token")
+            .with_fix(FixIt::insert(Span::new_with_unit(5, 0, Id::Main), ";"))
+            .map_spans(|span| Span::new_with_unit(span.start() + 10, span.len(), Id::Main));
+
+        assert_eq!(diagnostic.span, Span::new_with_unit(12, 3, Id::Main));
+        assert_eq!(diagnostic.labels[0].span, Span::new_with_unit(12, 3, Id::Main));
+        assert_eq!(diagnostic.fixes[0].span, Span::new_with_unit(15, 0, Id::Main));
+    }
+
+    #[test]
+    fn map_spans_drops_fixes_inside_expansions() {
+        let expansion = Expansion { name: "W".to_string(), definition: Span::new_with_unit(9, 3, Id::Main) };
+        let diagnostic = Diagnostic::error(Span::new_with_unit(2, 3, Id::Main), "expected ';'")
+            .with_fix(FixIt::insert(Span::new_with_unit(5, 0, Id::Main), ";"))
+            .map_spans(|span| span.clone().with_expansions(vec![expansion.clone()]));
+
+        assert!(diagnostic.fixes.is_empty());
+    }
+
+    #[test]
+    fn expansions_become_labels() {
+        let definition = Span::new_with_unit(0, 1, Id::Main);
+        let span = Span::new_with_unit(6, 1, Id::Main).with_expansions(vec![Expansion { name: "N".to_string(), definition }]);
+        let diagnostic = Diagnostic::error(span, "Unknown token.");
+        let file = SourceFile::from_string(Id::Main, "N @\nint @");
+
+        let mut rendered = Vec::new();
+        diagnostic.report().write(&file, &mut rendered).unwrap();
+
+        assert!(String::from_utf8(rendered).unwrap().contains("note: expanded from macro 'N'"));
+        assert_eq!(diagnostic.to_json(&file)["expansions"][0]["macro"], "N");
+    }
+
+    #[test]
+    fn labels_use_byte_offsets() {
+        let file = SourceFile::from_string(Id::Main, "/* é */ @");
+        let diagnostic = Diagnostic::error(Span::new_with_unit(9, 1, Id::Main), "Unknown token.")
+            .with_label(Span::new_with_unit(9, 1, Id::Main), "here");
+
+        let mut rendered = Vec::new();
+        diagnostic.report().write(&file, &mut rendered).unwrap();
+
+        assert!(String::from_utf8(rendered).unwrap().contains("Main:1:9"));
+        assert_eq!(diagnostic.to_json(&file)["column"], 9);
+    }
+
+    #[test]
+    fn json_has_location_and_code() {
+        let file = SourceFile::from_string(Id::Main, "int\n  @");
+        let diagnostic = Diagnostic::error(Span::new_with_unit(6, 1, Id::Main), "Unknown token.")
+            .with_code(ErrorCode::UnknownToken);
+
+        assert_eq!(diagnostic.to_json(&file).to_string(),
+            r#"{"byte_end":7,"byte_start":6,"code":"L003","column":3,"expansions":[],"file":"(buffer)","fixes":[],"line":2,"message":"Unknown token.","severity":"error","warning":null}"#);
+    }
+
+    #[test]
+    fn json_places_end_of_file_on_the_last_line() {
+        let file = SourceFile::from_string(Id::Main, "int main(void) {\n  return 0");
+        let diagnostic = Diagnostic::error(Span::new_with_unit(28, 0, Id::Main), "Unexpected end of file.");
+
+        assert_eq!(diagnostic.to_json(&file)["line"], 2);
+        assert_eq!(diagnostic.to_json(&file)["column"], 11);
+    }
+
+    #[test]
+    fn fixes_are_applied_in_order() {
+        let fixes = vec![
+            FixIt::insert(Span::new_with_unit(8, 0, Id::Main), ";"),
+            FixIt::insert(Span::new_with_unit(0, 0, Id::Main), "int "),
+            FixIt { span: Span::new_with_unit(7, 1, Id::Main), replacement: "1".to_string() },
+            FixIt { span: Span::new_with_unit(7, 2, Id::Main), replacement: "".to_string() },
+        ];
+
+        assert_eq!(apply_fixes("return 0 }", &[]), "return 0 }");
+        assert_eq!(apply_fixes("return 0 }", &fixes), "int return 1; }");
+    }
+}
@@ -0,0 +1,335 @@
+use std::fmt::{Display, Formatter};
+
+// Codes are stable: once published a code keeps its meaning, new diagnostics get new codes.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ErrorCode {
+    UnterminatedComment,
+    NestedComment,
+    UnknownToken,
+    UnterminatedLiteral,
+    UnexpectedToken,
+    UnexpectedEof,
+    InvalidConstant,
+    ExpectedEof,
+    IncludeNotFound,
+    ErrorDirective,
+    InvalidDirective,
+    UnterminatedConditional,
+    UnmatchedConditional,
+    MacroArguments,
+    InvalidCondition,
+    InvalidPaste,
+    IncludeTooDeep,
+}
+
+impl ErrorCode {
+    pub const ALL: &'static [ErrorCode] = &[
+        ErrorCode::UnterminatedComment,
+        ErrorCode::NestedComment,
+        ErrorCode::UnknownToken,
+        ErrorCode::UnterminatedLiteral,
+        ErrorCode::UnexpectedToken,
+        ErrorCode::UnexpectedEof,
+        ErrorCode::InvalidConstant,
+        ErrorCode::ExpectedEof,
+        ErrorCode::IncludeNotFound,
+        ErrorCode::ErrorDirective,
+        ErrorCode::InvalidDirective,
+        ErrorCode::UnterminatedConditional,
+        ErrorCode::UnmatchedConditional,
+        ErrorCode::MacroArguments,
+        ErrorCode::InvalidCondition,
+        ErrorCode::InvalidPaste,
+        ErrorCode::IncludeTooDeep,
+    ];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorCode::UnterminatedComment => "L001",
+            ErrorCode::NestedComment => "L002",
+            ErrorCode::UnknownToken => "L003",
+            ErrorCode::UnterminatedLiteral => "L004",
+            ErrorCode::UnexpectedToken => "P001",
+            ErrorCode::UnexpectedEof => "P002",
+            ErrorCode::InvalidConstant => "P003",
+            ErrorCode::ExpectedEof => "P004",
+            ErrorCode::IncludeNotFound => "D001",
+            ErrorCode::ErrorDirective => "D002",
+            ErrorCode::InvalidDirective => "D003",
+            ErrorCode::UnterminatedConditional => "D004",
+            ErrorCode::UnmatchedConditional => "D005",
+            ErrorCode::MacroArguments => "D006",
+            ErrorCode::InvalidCondition => "D007",
+            ErrorCode::InvalidPaste => "D008",
+            ErrorCode::IncludeTooDeep => "D009",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<ErrorCode> {
+        ErrorCode::ALL.iter().copied().find(|c| c.code().eq_ignore_ascii_case(code))
+    }
+
+    pub fn explanation(&self) -> &'static str {
+        match self {
+            ErrorCode::UnterminatedComment => L001,
+            ErrorCode::NestedComment => L002,
+            ErrorCode::UnknownToken => L003,
+            ErrorCode::UnterminatedLiteral => L004,
+            ErrorCode::UnexpectedToken => P001,
+            ErrorCode::UnexpectedEof => P002,
+            ErrorCode::InvalidConstant => P003,
+            ErrorCode::ExpectedEof => P004,
+            ErrorCode::IncludeNotFound => D001,
+            ErrorCode::ErrorDirective => D002,
+            ErrorCode::InvalidDirective => D003,
+            ErrorCode::UnterminatedConditional => D004,
+            ErrorCode::UnmatchedConditional => D005,
+            ErrorCode::MacroArguments => D006,
+            ErrorCode::InvalidCondition => D007,
+            ErrorCode::InvalidPaste => D008,
+            ErrorCode::IncludeTooDeep => D009,
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+static L001: &str = r"A block comment was opened but the file ended before it was closed.
+
+Erroneous code example:
+
+    int main(void) {
+        return 0; /* the rest of the file is now a comment
+    }
+
+Every `/*` must be matched by a `*/`. Close the comment where it was meant to
+end:
+
+    int main(void) {
+        return 0; /* done */
+    }
+";
+
+static L002: &str = r"A block comment contains `/*`. This is a warning, enabled with -Wcomment.
+
+Example:
+
+    /* outer /* inner */ still a comment? */
+
+C block comments do not nest: the first `*/` ends the comment, so the text
+after it is lexed as code. Remove the inner `/*`, or use `//` comments for the
+inner part. Code written for compilers where comments nest can be compiled
+with `--nested-comments`, where every `/*` needs its own `*/`.
+";
+
+static L003: &str = r"The lexer found characters that do not start any C token.
+
+Erroneous code example:
+
+    int main(void) {
+        return 0@1;
+    }
+
+Only the characters of the C source character set can appear outside of
+comments. Adjacent unknown characters are reported as a single span. Remove
+the characters, or move them into a comment.
+";
+
+static L004: &str = r#"A string literal or character constant is missing its closing quote.
+
+Erroneous code example:
+
+    int main(void) {
+        return 'a;
+    }
+
+Literals end at the matching quote on the same line; a backslash lets the
+quote appear inside, as in `'\''` or `"say \"hi\""`. Add the closing quote.
+"#;
+
+static P001: &str = r"The parser found a token that cannot appear at this point.
+
+Erroneous code example:
+
+    int main(void) {
+        return 0
+    }
+
+Here a `;` is required to end the return statement, but `}` was found. The
+diagnostic shows the token that was expected; insert it, or remove the
+unexpected token.
+";
+
+static P002: &str = r"The file ended while the parser was still expecting more tokens.
+
+Erroneous code example:
+
+    int main(void) {
+        return 0;
+
+The function body is never closed. This usually means a missing `}` or `;` at
+the end of the file, or an earlier construct that was left unfinished.
+";
+
+static P003: &str = r"An integer constant could not be converted to a value.
+
+Erroneous code example:
+
+    int main(void) {
+        return 0x1G + 09 + 99999999999999999999;
+    }
+
+Integer constants are decimal, octal when they start with 0 (017), hexadecimal with
+0x (0x1F) or binary with 0b (0b101). Digits may be separated by ' (1'000), and the
+constant may end in u, l or ll, or u together with l or ll (123ULL). A digit that
+does not belong to the base, any other suffix, or a value too large for every type
+the suffix allows is an error. Floating constants are not supported yet.
+";
+
+static P004: &str = r"Tokens were found after the end of the program.
+
+Erroneous code example:
+
+    int main(void) {
+        return 0;
+    }
+    }
+
+The program was complete before these tokens. Remove them, or check for an
+unbalanced brace earlier in the file.
+";
+
+static D001: &str = r#"A header named in `#include` could not be found.
+
+Erroneous code example:
+
+    #include "missing.h"
+
+Headers written with quotes are looked up next to the including file first,
+then in the directories given with `-I`. Headers written with angle brackets
+are only looked up in the `-I` directories. Check the spelling of the name or
+add the directory that contains it with `-I`.
+"#;
+
+static D002: &str = r#"An `#error` directive was reached.
+
+Erroneous code example:
+
+    #ifndef CONFIG
+    #error "CONFIG must be defined"
+    #endif
+
+`#error` stops compilation with the given message. It is usually guarded by a
+condition; define what the condition checks for, or remove the directive.
+"#;
+
+static D003: &str = r"A preprocessing directive is unknown or malformed.
+
+Erroneous code example:
+
+    #defne X 1
+    #define 1X
+
+The name after `#` must be one of the known directives, and each directive
+has its own syntax: `#define` needs an identifier, `#include` a header name,
+`#line` a line number, and so on.
+";
+
+static D004: &str = r"A conditional directive was never closed.
+
+Erroneous code example:
+
+    #ifdef DEBUG
+    int debug(void) { return 1; }
+
+Every `#if`, `#ifdef` and `#ifndef` needs a matching `#endif` in the same
+file.
+";
+
+static D005: &str = r"A conditional directive has no `#if` to belong to.
+
+Erroneous code example:
+
+    #endif
+    #if A
+    #else
+    #elif B
+    #endif
+
+`#elif`, `#else` and `#endif` must follow an open `#if`, `#ifdef` or
+`#ifndef`, and nothing but `#endif` may follow `#else`.
+";
+
+static D006: &str = r"A function-like macro was invoked with the wrong arguments.
+
+Erroneous code example:
+
+    #define MAX(a, b) ((a) > (b) ? (a) : (b))
+    int x = MAX(1);
+
+The invocation must pass exactly as many arguments as the macro has
+parameters (or at least as many, for a variadic macro), and its argument list
+must be closed with `)`.
+";
+
+static D007: &str = r"The condition of an `#if` or `#elif` is not a valid constant expression.
+
+Erroneous code example:
+
+    #if 1 +
+    #endif
+
+After macro expansion, the condition may only contain integer and character
+constants, `defined` operators and C operators. Identifiers that are not
+macros count as 0. Division by zero is an error.
+";
+
+static D008: &str = r"The `##` operator produced something that is not a single token.
+
+Erroneous code example:
+
+    #define CAT(a, b) a ## b
+    CAT(+, /)
+
+Pasting must result in one valid preprocessing token, such as an identifier
+made of two halves. Pasting `+` and `/` gives `+/`, which is two tokens.
+";
+
+static D009: &str = r#"Headers include each other too deeply.
+
+Erroneous code example:
+
+    // a.h
+    #include "a.h"
+
+This usually means a header includes itself, directly or through other
+headers. Add an include guard:
+
+    #ifndef A_H
+    #define A_H
+    ...
+    #endif
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_are_unique_and_round_trip() {
+        for code in ErrorCode::ALL {
+            assert_eq!(ErrorCode::from_code(code.code()), Some(*code));
+            assert_eq!(ErrorCode::ALL.iter().filter(|c| c.code() == code.code()).count(), 1);
+        }
+    }
+
+    #[test]
+    fn lookup_ignores_case() {
+        assert_eq!(ErrorCode::from_code("l002"), Some(ErrorCode::NestedComment));
+        assert_eq!(ErrorCode::from_code("X999"), None);
+    }
+}
@@ -0,0 +1,46 @@
+// What makes up an identifier, shared by the preprocessor and the lexer so both split the
+// text the same way.
+
+pub fn is_identifier_start(c: u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'_'
+}
+
+pub fn is_identifier_continue(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+// Length of the universal character name (`\u` and four hex digits, or `\U` and eight) at
+// the start of `bytes`, if it names a character allowed in identifiers.
+pub fn universal_character_name(bytes: &[u8]) -> Option<usize> {
+    let digits = match bytes {
+        [b'\\', b'u', ..] => 4,
+        [b'\\', b'U', ..] => 8,
+        _ => return None,
+    };
+    let hex = bytes.get(2..2 + digits).filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+    let value = hex.iter().fold(0, |value, &c| value * 16 + (c as char).to_digit(16).unwrap_or(0));
+
+    // the basic character set has to be written as itself, except for `$`, `@` and `` ` ``
+    let allowed = (value >= 0xa0 || matches!(value, 0x24 | 0x40 | 0x60))
+        && !(0xd800..=0xdfff).contains(&value)
+        && value <= 0x10ffff;
+
+    allowed.then_some(2 + digits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn universal_character_names() {
+        assert_eq!(universal_character_name(b"\\u00e9x"), Some(6));
+        assert_eq!(universal_character_name(b"\\U0001F600"), Some(10));
+        assert_eq!(universal_character_name(b"\\u0024"), Some(6));
+        // basic characters, surrogates, out of range and short
+        assert_eq!(universal_character_name(b"\\u0041"), None);
+        assert_eq!(universal_character_name(b"\\ud800"), None);
+        assert_eq!(universal_character_name(b"\\U00110000"), None);
+        assert_eq!(universal_character_name(b"\\u00e"), None);
+    }
+}
@@ -1,2 +1,7 @@
 pub mod source_file;
-pub mod span;
\ No newline at end of file
+pub mod span;
+pub mod diagnostics;
+pub mod error_codes;
+pub mod warnings;
+pub mod source_map;
+pub mod identifiers;
\ No newline at end of file
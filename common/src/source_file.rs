@@ -1,10 +1,12 @@
 use std::fmt::{Debug, Display};
 use std::fs::File;
 use std::io::{BufReader, Error, Read};
+use std::ops::Range;
+use std::sync::OnceLock;
 use ariadne::{Cache, Source};
 use crate::span::Span;
 
-#[derive(Eq, PartialEq, Clone, Debug)]
+#[derive(Eq, PartialEq, Hash, Clone, Debug)]
 pub enum Id {
     Main,
     Unit(String),
@@ -19,11 +21,41 @@ impl Display for Id {
     }
 }
 
+// The bytes in `range` replaced with `replacement`, as an editor sends them.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub replacement: String,
+}
+
+impl TextEdit {
+    pub fn new(range: Range<usize>, replacement: impl Into<String>) -> TextEdit {
+        TextEdit { range, replacement: replacement.into() }
+    }
+
+    // Where the replacement ends in the edited text.
+    pub fn new_end(&self) -> usize {
+        self.range.start + self.replacement.len()
+    }
+
+    // Where an offset at or after the end of `range` moves to in the edited text.
+    pub fn shift(&self, offset: usize) -> usize {
+        offset + self.replacement.len() - self.range.len()
+    }
+
+    // The inverse of `shift`, for offsets at or after `new_end`.
+    pub fn unshift(&self, offset: usize) -> usize {
+        offset + self.range.len() - self.replacement.len()
+    }
+}
+
 #[derive()]
 pub struct SourceFile {
     pub id: Id,
     pub filename: String,
-    pub source: Source,
+    text: String,
+    // for reports, only built when one is written, as it indexes every line again
+    source: OnceLock<Source>,
     lines_offsets: Vec<usize>,
     pub length: usize,
 }
@@ -46,7 +78,8 @@ impl SourceFile {
                         Ok(Self {
                             id,
                             filename: filename.to_string(),
-                            source: Source::from(contents),
+                            text: contents,
+                            source: OnceLock::new(),
                             length: size,
                             lines_offsets: offsets,
                         })
@@ -59,12 +92,13 @@ impl SourceFile {
     }
 
     pub fn from_string(id: Id, input: &str) -> Self {
-        let offsets = Self::offsets(&input);
+        let offsets = Self::offsets(input);
 
         SourceFile {
             id,
             filename: "(buffer)".parse().unwrap(),
-            source: Source::from(input.to_string()),
+            text: input.to_string(),
+            source: OnceLock::new(),
             lines_offsets: offsets,
             length: input.len(),
         }
@@ -82,16 +116,64 @@ impl SourceFile {
         offsets
     }
     
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn source(&self) -> &Source {
+        self.source.get_or_init(|| Source::from(self.text.clone()))
+    }
+
     pub fn set_id(&mut self, id: Id) {
         self.id = id;
     }
 
+    // 1-based line and column of the byte at `offset`, counting the column in characters.
     pub fn line_pos_from_offset(&self, offset: usize) -> Option<(usize, usize)> {
+        let (line, byte_column) = self.line_and_byte_column(offset)?;
+        let line_start = offset + 1 - byte_column;
+        let column = match self.text().get(line_start..offset) {
+            Some(before) => before.chars().count() + 1,
+            None => byte_column,
+        };
+
+        Some((line, column))
+    }
+
+    // Like `line_pos_from_offset`, but an offset at or past the end of the text is placed just
+    // after its last character, where an unexpected end of file is reported.
+    pub fn line_pos_or_end(&self, offset: usize) -> (usize, usize) {
+        if let Some(position) = self.line_pos_from_offset(offset) {
+            return position;
+        }
+
+        let text = self.text();
+        let line_start = self.lines_offsets.last().map_or(0, |newline| newline + 1);
+
+        (self.lines_offsets.len() + 1, text[line_start.min(text.len())..].chars().count() + 1)
+    }
+
+    // Byte offset of a 1-based line and character column, the inverse of `line_pos_from_offset`.
+    // A column past the end of the line gives the end of the line.
+    pub fn offset_from_line_pos(&self, line: usize, column: usize) -> Option<usize> {
+        let text = self.text();
+        let start = match line {
+            0 => return None,
+            1 => 0,
+            _ => self.lines_offsets.get(line - 2)? + 1,
+        };
+        let end = self.lines_offsets.get(line - 1).copied().unwrap_or(text.len());
+        let line_text = &text[start..end];
+
+        Some(start + line_text.char_indices().nth(column.saturating_sub(1)).map_or(line_text.len(), |(index, _)| index))
+    }
+
+    fn line_and_byte_column(&self, offset: usize) -> Option<(usize, usize)> {
         if self.length == 0 {
             return None;
         }
 
-        if self.lines_offsets.len() > 0 {
+        if !self.lines_offsets.is_empty() {
             // handle the first line
             if offset <= self.lines_offsets[0] {
                 return Some((1, offset + 1));
@@ -117,8 +199,27 @@ impl SourceFile {
         None
     }
 
+    // Applies an edit in place, updating the line table for the lines it touches instead of
+    // rescanning the whole text.
+    pub fn apply(&mut self, edit: &TextEdit) {
+        self.text.replace_range(edit.range.clone(), &edit.replacement);
+
+        let first = self.lines_offsets.partition_point(|&offset| offset < edit.range.start);
+        let last = self.lines_offsets.partition_point(|&offset| offset < edit.range.end);
+
+        for offset in &mut self.lines_offsets[last..] {
+            *offset = edit.shift(*offset);
+        }
+
+        let inserted = Self::offsets(&edit.replacement).into_iter().map(|offset| edit.range.start + offset);
+        self.lines_offsets.splice(first..last, inserted);
+
+        self.length = self.text.len();
+        self.source = OnceLock::new();
+    }
+
     pub fn get_text(&self, span: &Span) -> &str {
-        &self.source.text()[span.range()]
+        &self.text()[span.range()]
     }
 
     pub fn eof(&self) -> Span {
@@ -130,7 +231,7 @@ impl Cache<Id> for SourceFile {
     type Storage = String;
 
     fn fetch(&mut self, _id: &Id) -> Result<&Source<Self::Storage>, impl Debug> {
-        Ok::<_, Error>(&self.source)
+        Ok::<_, Error>(self.source())
     }
 
     fn display<'a>(&self, id: &'a Id) -> Option<impl Display + 'a> {
@@ -142,7 +243,7 @@ impl Cache<Id> for &SourceFile {
     type Storage = String;
 
     fn fetch(&mut self, _id: &Id) -> Result<&Source<Self::Storage>, impl Debug> {
-        Ok::<_, Error>(&self.source)
+        Ok::<_, Error>(self.source())
     }
 
     fn display<'a>(&self, id: &'a Id) -> Option<impl Display + 'a> {
@@ -181,6 +282,49 @@ mod tests {
         assert_eq!(case1.unwrap(), (1, 1));
     }
 
+    #[test]
+    fn offsets_from_line_positions() {
+        let fm = SourceFile::from_string(Id::Main, "ab\né1\n\nxyz");
+
+        assert_eq!(fm.offset_from_line_pos(1, 1), Some(0));
+        assert_eq!(fm.offset_from_line_pos(2, 2), Some(5));
+        assert_eq!(fm.offset_from_line_pos(2, 9), Some(6));
+        assert_eq!(fm.offset_from_line_pos(3, 1), Some(7));
+        assert_eq!(fm.offset_from_line_pos(4, 3), Some(10));
+        assert_eq!(fm.offset_from_line_pos(5, 1), None);
+
+        for offset in [0, 3, 5, 8, 10] {
+            let (line, column) = fm.line_pos_from_offset(offset).unwrap();
+            assert_eq!(fm.offset_from_line_pos(line, column), Some(offset));
+        }
+    }
+
+    #[test]
+    fn end_of_file_has_a_position() {
+        assert_eq!(SourceFile::from_string(Id::Main, TEST1).line_pos_or_end(TEST1.len()), (4, 1));
+        assert_eq!(SourceFile::from_string(Id::Main, TEST2).line_pos_or_end(TEST2.len() + 5), (4, 4));
+        assert_eq!(SourceFile::from_string(Id::Main, "é").line_pos_or_end(2), (1, 2));
+        assert_eq!(SourceFile::from_string(Id::Main, TEST3).line_pos_or_end(0), (1, 1));
+        assert_eq!(SourceFile::from_string(Id::Main, TEST4).line_pos_or_end(2), (1, 3));
+    }
+
+    #[test]
+    fn edits_keep_the_line_table() {
+        let mut fm = SourceFile::from_string(Id::Main, "ab\ncd\nef\ngh");
+
+        for (range, replacement) in [(1..7, "X\nY"), (0..0, "\n\n"), (9..9, "\nZ"), (2..5, "")] {
+            fm.apply(&TextEdit::new(range, replacement));
+            let expected = SourceFile::from_string(Id::Main, fm.text());
+
+            assert_eq!(fm.lines_offsets, expected.lines_offsets);
+            assert_eq!(fm.length, expected.length);
+            // the report source follows the edits
+            assert_eq!(fm.source().text(), fm.text());
+        }
+
+        assert_eq!(fm.text(), "\n\nYf\ng\nZh");
+    }
+
     #[test]
     fn file_map_should_get_first_line_positions() {
         let fm = SourceFile::from_string(Id::Main, TEST1);
@@ -230,4 +374,13 @@ mod tests {
         assert_eq!(fm.line_pos_from_offset(0).unwrap(), (1, 1));
         assert_eq!(fm.line_pos_from_offset(5), None);
     }
+
+    #[test]
+    fn file_map_should_count_columns_in_characters() {
+        let fm = SourceFile::from_string(Id::Main, "é\n/* ü */ x");
+
+        assert_eq!(fm.line_pos_from_offset(0).unwrap(), (1, 1));
+        assert_eq!(fm.line_pos_from_offset(2).unwrap(), (1, 2));
+        assert_eq!(fm.line_pos_from_offset(12).unwrap(), (2, 9));
+    }
 }
@@ -0,0 +1,67 @@
+use std::fmt::{Debug, Display};
+use ariadne::{Cache, Source};
+use crate::source_file::{Id, SourceFile};
+
+// All the files of a compilation, so a diagnostic can be rendered against whichever
+// file its spans point into.
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap { files: Vec::new() }
+    }
+
+    pub fn add(&mut self, file: SourceFile) {
+        self.files.push(file);
+    }
+
+    pub fn get(&self, id: &Id) -> Option<&SourceFile> {
+        self.files.iter().find(|file| &file.id == id)
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+}
+
+impl Cache<Id> for &SourceMap {
+    type Storage = String;
+
+    fn fetch(&mut self, id: &Id) -> Result<&Source<Self::Storage>, impl Debug> {
+        match self.get(id) {
+            Some(file) => Ok(file.source()),
+            None => Err(format!("no source file for {}", id)),
+        }
+    }
+
+    fn display<'a>(&self, id: &'a Id) -> Option<impl Display + 'a> {
+        Some(match self.get(id) {
+            Some(file) => file.filename.clone(),
+            None => id.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serves_the_file_for_each_id() {
+        let first = Id::Unit("first.c".to_string());
+        let second = Id::Unit("second.c".to_string());
+
+        let mut map = SourceMap::new();
+        map.add(SourceFile::from_string(first.clone(), "int"));
+        map.add(SourceFile::from_string(second.clone(), "void"));
+
+        let mut cache = &map;
+
+        assert_eq!(cache.fetch(&first).unwrap().text(), "int");
+        assert_eq!(cache.fetch(&second).unwrap().text(), "void");
+        assert!(cache.fetch(&Id::Main).is_err());
+    }
+}
@@ -2,30 +2,46 @@ use std::cmp::Ordering;
 use std::fmt;
 use std::fmt::Display;
 use std::ops::Range;
+use std::sync::Arc;
 use ariadne::Span as ASpan;
 use crate::source_file::Id;
 
+// A macro that text went through on its way to a span: the macro's name and where the
+// text is written in its definition.
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct Expansion {
+    pub name: String,
+    pub definition: Span,
+}
+
 #[derive(Debug, PartialEq, Clone, Eq)]
 pub struct Span {
     _unit: Id,
     _start: usize,
-    _len: usize
+    _len: usize,
+    // innermost macro first, for spans of text that came out of macro expansion
+    _expansions: Option<Arc<Vec<Expansion>>>,
 }
 
 impl Span {
-    pub fn new(start: usize, len: usize) -> Span {
-        Span { _unit: Id::Main, _start: start, _len: len }
+    pub fn new_with_unit(start: usize, len: usize, unit: Id) -> Span {
+        Span { _unit: unit, _start: start, _len: len, _expansions: None }
     }
 
-    pub fn new_with_unit(start: usize, len: usize, unit: Id) -> Span {
-        Span { _unit: unit, _start: start, _len: len }
+    pub fn with_expansions(mut self, expansions: Vec<Expansion>) -> Span {
+        self._expansions = if expansions.is_empty() { None } else { Some(Arc::new(expansions)) };
+        self
     }
 
-    pub fn combine_ranges(r1: Range<usize>, r2: Range<usize>) -> Span {
+    pub fn expansions(&self) -> &[Expansion] {
+        self._expansions.as_deref().map_or(&[], Vec::as_slice)
+    }
+
+    pub fn combine_ranges(r1: Range<usize>, r2: Range<usize>, unit: Id) -> Span {
         if r1.start < r2.start {
-            Span::new(r1.start, r2.end - r1.start)
+            Span::new_with_unit(r1.start, r2.end - r1.start, unit)
         } else {
-            Span::new(r2.start, r1.end- r2.start)
+            Span::new_with_unit(r2.start, r1.end- r2.start, unit)
         }
     }
 
@@ -33,14 +49,23 @@ impl Span {
         self._len += len;
     }
 
+    // Moves the span to start at `start`, keeping its length and unit.
+    pub fn move_to(&mut self, start: usize) {
+        self._start = start;
+    }
+
     pub fn range(&self) -> Range<usize> {
         self._start..self._start + self._len
     }
-}
 
-impl From<Range<usize>> for Span {
-    fn from(r: Range<usize>) -> Span {
-        Span::new(r.start, r.end - r.start)
+    // An empty span just past the end of this one, in the same unit.
+    pub fn after(&self) -> Span {
+        Span::new_with_unit(self.end(), 0, self._unit.clone())
+    }
+
+    // The part of this span at `range`, counted from its start.
+    pub fn sub_span(&self, range: Range<usize>) -> Span {
+        Span { _start: self._start + range.start, _len: range.end - range.start, ..self.clone() }
     }
 }
 
@@ -95,15 +120,32 @@ mod tests {
         assert_eq!(range2.start, 7);
         assert_eq!(range2.end, 8);
 
-        let span = Span::combine_ranges(range1, range2);
+        let span = Span::combine_ranges(range1, range2, Id::Main);
 
         assert_eq!(span._start, 3);
         assert_eq!(span._len, 5);
     }
 
+    #[test]
+    fn test_after() {
+        let span = Span::new_with_unit(3, 2, Id::Unit("a.c".to_string()));
+
+        assert_eq!(span.after(), Span::new_with_unit(5, 0, Id::Unit("a.c".to_string())));
+    }
+
+    #[test]
+    fn test_expansions() {
+        let definition = Span::new_with_unit(8, 1, Id::Unit("a.h".to_string()));
+        let span = Span::new_with_unit(3, 2, Id::Main).with_expansions(vec![Expansion { name: "N".to_string(), definition: definition.clone() }]);
+
+        assert_eq!(span.expansions()[0].definition, definition);
+        assert_ne!(span, Span::new_with_unit(3, 2, Id::Main));
+        assert!(Span::new_with_unit(3, 2, Id::Main).with_expansions(Vec::new()).expansions().is_empty());
+    }
+
     #[test]
     fn test_range() {
-        let span1 = Span::new(0, 2);
+        let span1 = Span::new_with_unit(0, 2, Id::Main);
 
         assert_eq!(span1.range(), 0..2);
     }
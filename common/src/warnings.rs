@@ -0,0 +1,166 @@
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use crate::diagnostics::{Diagnostic, Severity};
+
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+pub enum Warning {
+    UnusedVariable,
+    UnusedLabel,
+    ImplicitConversion,
+    MissingReturn,
+    SignCompare,
+    MacroRedefined,
+    Comment,
+}
+
+impl Warning {
+    pub const ALL: &'static [Warning] = &[
+        Warning::UnusedVariable,
+        Warning::UnusedLabel,
+        Warning::ImplicitConversion,
+        Warning::MissingReturn,
+        Warning::SignCompare,
+        Warning::MacroRedefined,
+        Warning::Comment,
+    ];
+
+    // The name used on the command line, as in `-Wunused-variable`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Warning::UnusedVariable => "unused-variable",
+            Warning::UnusedLabel => "unused-label",
+            Warning::ImplicitConversion => "conversion",
+            Warning::MissingReturn => "return-type",
+            Warning::SignCompare => "sign-compare",
+            Warning::MacroRedefined => "macro-redefined",
+            Warning::Comment => "comment",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Warning> {
+        Warning::ALL.iter().copied().find(|w| w.name() == name)
+    }
+
+    pub fn enabled_by_default(&self) -> bool {
+        matches!(self, Warning::MissingReturn | Warning::MacroRedefined)
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "-W{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WarningOptions {
+    enabled: HashSet<Warning>,
+    pub as_errors: bool,
+}
+
+impl Default for WarningOptions {
+    fn default() -> Self {
+        WarningOptions {
+            enabled: Warning::ALL.iter().copied().filter(Warning::enabled_by_default).collect(),
+            as_errors: false,
+        }
+    }
+}
+
+impl WarningOptions {
+    // Takes the values of `-W` flags without the prefix (`all`, `error`, `no-unused-label`, ...),
+    // applied in order so later flags override earlier ones.
+    pub fn from_flags<S: AsRef<str>>(flags: &[S]) -> Result<WarningOptions, String> {
+        let mut options = WarningOptions::default();
+
+        for flag in flags {
+            let flag = flag.as_ref();
+            let (name, enable) = match flag.strip_prefix("no-") {
+                Some(name) => (name, false),
+                None => (flag, true),
+            };
+
+            match name {
+                "all" => {
+                    for warning in Warning::ALL {
+                        options.set(*warning, enable);
+                    }
+                }
+                "error" => options.as_errors = enable,
+                _ => match Warning::from_name(name) {
+                    Some(warning) => options.set(warning, enable),
+                    None => return Err(format!("unknown warning option '-W{}'", flag)),
+                },
+            }
+        }
+
+        Ok(options)
+    }
+
+    pub fn set(&mut self, warning: Warning, enable: bool) {
+        if enable {
+            self.enabled.insert(warning);
+        } else {
+            self.enabled.remove(&warning);
+        }
+    }
+
+    pub fn is_enabled(&self, warning: Warning) -> bool {
+        self.enabled.contains(&warning)
+    }
+
+    // The diagnostic as it is reported under these options: none for a disabled warning, and
+    // an error for any other warning under -Werror.
+    pub fn apply(&self, diagnostic: Diagnostic) -> Option<Diagnostic> {
+        match diagnostic.warning {
+            Some(warning) if !self.is_enabled(warning) => None,
+            Some(_) if self.as_errors => Some(Diagnostic { severity: Severity::Error, ..diagnostic }),
+            _ => Some(diagnostic),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::source_file::Id;
+    use crate::span::Span;
+    use super::*;
+
+    #[test]
+    fn defaults() {
+        let options = WarningOptions::default();
+
+        assert!(options.is_enabled(Warning::MissingReturn));
+        assert!(!options.is_enabled(Warning::UnusedVariable));
+        assert!(!options.as_errors);
+    }
+
+    #[test]
+    fn later_flags_override_earlier() {
+        let options = WarningOptions::from_flags(&["all", "no-unused-label", "error"]).unwrap();
+
+        assert!(options.is_enabled(Warning::UnusedVariable));
+        assert!(!options.is_enabled(Warning::UnusedLabel));
+        assert!(options.as_errors);
+    }
+
+    #[test]
+    fn applied_to_diagnostics() {
+        let span = Span::new_with_unit(0, 1, Id::Main);
+        let warning = Diagnostic::warning(Warning::UnusedVariable, span.clone(), "unused variable 'x'");
+        let error = Diagnostic::error(span, "expected ';'");
+        let options = WarningOptions::from_flags(&["error"]).unwrap();
+
+        assert!(options.apply(warning.clone()).is_none());
+        assert_eq!(options.apply(error.clone()).unwrap().severity, Severity::Error);
+
+        let options = WarningOptions::from_flags(&["unused-variable", "error"]).unwrap();
+        assert_eq!(options.apply(warning.clone()).unwrap().severity, Severity::Error);
+        assert_eq!(WarningOptions::from_flags(&["unused-variable"]).unwrap().apply(warning).unwrap().severity, Severity::Warning);
+    }
+
+    #[test]
+    fn unknown_flag() {
+        assert_eq!(WarningOptions::from_flags(&["bogus"]).unwrap_err(), "unknown warning option '-Wbogus'");
+    }
+}
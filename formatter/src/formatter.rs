@@ -0,0 +1,381 @@
+use std::str::FromStr;
+use common::diagnostics::{Diagnostic, Severity};
+use common::source_file::Id;
+use common::span::Span;
+use lexer::lexer::Lexer;
+use lexer::tokens::TokenKind;
+use parser::parser::Parser;
+use parser::syntax::{NodeKind, SyntaxNode, SyntaxToken};
+use parser::token_collection::TokenCollection;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BraceStyle {
+    // `int main(void) {`
+    SameLine,
+    // the opening brace of a function body on a line of its own
+    NextLine,
+}
+
+impl FromStr for BraceStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "same-line" => Ok(BraceStyle::SameLine),
+            "next-line" => Ok(BraceStyle::NextLine),
+            _ => Err(format!("unknown brace style '{}', expected 'same-line' or 'next-line'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    pub indent_width: usize,
+    pub brace_style: BraceStyle,
+    // a declarator longer than this gets its parameters on lines of their own
+    pub line_width: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> FormatOptions {
+        FormatOptions { indent_width: 4, brace_style: BraceStyle::SameLine, line_width: 80 }
+    }
+}
+
+// What goes between two pieces of output, from least to most space.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+enum Separator {
+    Nothing,
+    Space,
+    Newline,
+    BlankLine,
+}
+
+// Reprints `text` in the house style. Source that doesn't lex or parse is left alone and its
+// errors are returned instead, with spans in `unit`.
+//
+// The text is formatted as written, without preprocessing it: directives are kept as they
+// are, but macros aren't expanded and lines an `#if` leaves out are parsed like any other.
+// Code that only parses once preprocessed, like `return ZERO;` or a body with a `#if 0`
+// block of notes, can't be formatted.
+pub fn format(text: &str, unit: &Id, options: &FormatOptions) -> Result<String, Vec<Diagnostic>> {
+    let (tokens, lexer_errors) = Lexer::new_with_unit(text, unit.clone()).with_trivia(true).run();
+    let has_directives = tokens.iter().any(|token| token.kind == TokenKind::Directive);
+    let mut parser = Parser::new(TokenCollection::new(tokens), text);
+    let (_, parse_errors) = parser.run();
+    let eof = Span::new_with_unit(text.len(), 0, unit.clone());
+
+    let errors: Vec<Diagnostic> = lexer_errors.iter().map(|error| error.to_diagnostic())
+        .chain(parse_errors.iter().map(|error| error.to_diagnostic(eof.clone())))
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .map(|diagnostic| if has_directives {
+            diagnostic.with_note("the formatter reads the file as written: macros aren't expanded and code left out by `#if` still has to parse")
+        } else {
+            diagnostic
+        })
+        .collect();
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(Formatter::new(options).run(&parser.syntax_tree()))
+}
+
+struct Formatter<'a> {
+    options: &'a FormatOptions,
+    output: String,
+    // open braces
+    depth: usize,
+    // inside the parentheses of a declarator too long for one line
+    broken_parameters: bool,
+}
+
+// A token that gets printed, with the number of line breaks in front of it in the source.
+struct Piece {
+    token: SyntaxToken,
+    newlines: usize,
+}
+
+fn pieces(tree: &SyntaxNode) -> Vec<Piece> {
+    let mut pieces = Vec::new();
+    let mut newlines = 0;
+
+    for token in tree.tokens() {
+        if token.kind() == TokenKind::Whitespace {
+            newlines += token.text().matches('\n').count();
+        } else {
+            pieces.push(Piece { token, newlines });
+            newlines = 0;
+        }
+    }
+
+    pieces
+}
+
+fn parent_kind(token: &SyntaxToken) -> NodeKind {
+    token.parent().kind()
+}
+
+// The `{` or `}` of a function body.
+fn is_function_brace(token: &SyntaxToken) -> bool {
+    parent_kind(token) == NodeKind::CompoundStatement
+        && token.parent().parent().is_some_and(|node| node.kind() == NodeKind::FunctionDefinition)
+}
+
+impl<'a> Formatter<'a> {
+    fn new(options: &'a FormatOptions) -> Formatter<'a> {
+        Formatter { options, output: String::new(), depth: 0, broken_parameters: false }
+    }
+
+    fn run(mut self, tree: &SyntaxNode) -> String {
+        let pieces = pieces(tree);
+        let mut previous: Option<&SyntaxToken> = None;
+        let mut previous_significant: Option<&SyntaxToken> = None;
+
+        for piece in &pieces {
+            let token = &piece.token;
+            // blank lines right inside a block are dropped
+            let after_open_brace = previous_significant.is_some_and(|previous| previous.kind() == TokenKind::OpenBrace);
+
+            // the least a comment or directive in front of this piece calls for
+            let required = match previous {
+                Some(previous) if previous.kind() == TokenKind::Directive => Separator::Newline,
+                Some(previous) if previous.kind() == TokenKind::Comment && (previous.text().starts_with("//") || piece.newlines > 0) =>
+                    Separator::Newline,
+                Some(previous) if previous.kind() == TokenKind::Comment => Separator::Space,
+                _ => Separator::Nothing,
+            };
+
+            let own_line = if previous.is_none() {
+                Separator::Nothing
+            } else if piece.newlines >= 2 && !after_open_brace {
+                Separator::BlankLine
+            } else {
+                Separator::Newline
+            };
+
+            let (separator, indent) = match token.kind() {
+                TokenKind::Directive => (own_line, 0),
+                TokenKind::Comment if piece.newlines > 0 || previous.is_none() => (own_line, self.indent()),
+                // hugs an opening parenthesis like any token would
+                TokenKind::Comment if previous.is_some_and(|previous| previous.kind() == TokenKind::OpenParen) =>
+                    (Separator::Nothing, self.indent()),
+                TokenKind::Comment => (Separator::Space.max(required), self.indent()),
+                kind => {
+                    if kind == TokenKind::CloseBrace {
+                        self.depth = self.depth.saturating_sub(1);
+                    }
+
+                    let natural = previous_significant.map_or(Separator::Nothing, |previous| self.separator(previous, token));
+                    let mut separator = natural.max(required);
+
+                    if kind == TokenKind::CloseParen && parent_kind(token) == NodeKind::Declarator {
+                        self.broken_parameters = false;
+                    }
+
+                    if separator == Separator::Newline && piece.newlines >= 2 && !after_open_brace && kind != TokenKind::CloseBrace {
+                        separator = Separator::BlankLine;
+                    }
+
+                    (separator, self.indent())
+                }
+            };
+
+            self.write_separator(separator, indent);
+            self.write_token(token);
+
+            if !token.kind().is_trivia() {
+                match token.kind() {
+                    TokenKind::OpenBrace => self.depth += 1,
+                    TokenKind::OpenParen if parent_kind(token) == NodeKind::Declarator =>
+                        self.broken_parameters = !self.declarator_fits(token.parent()),
+                    _ => {}
+                }
+
+                previous_significant = Some(token);
+            }
+
+            previous = Some(token);
+        }
+
+        self.output.push('\n');
+        self.output
+    }
+
+    fn indent(&self) -> usize {
+        (self.depth + self.broken_parameters as usize) * self.options.indent_width
+    }
+
+    // What goes between two tokens next to each other in the parse, with nothing but
+    // whitespace between them.
+    fn separator(&self, previous: &SyntaxToken, next: &SyntaxToken) -> Separator {
+        use TokenKind::*;
+
+        let in_declarator = parent_kind(previous) == NodeKind::Declarator && parent_kind(next) == NodeKind::Declarator;
+
+        match (previous.kind(), next.kind()) {
+            (_, OpenBrace) if is_function_brace(next) => match self.options.brace_style {
+                BraceStyle::SameLine => Separator::Space,
+                BraceStyle::NextLine => Separator::Newline,
+            },
+            (CloseBrace, _) if is_function_brace(previous) => Separator::BlankLine,
+            (OpenParen, _) | (Comma, _) | (_, CloseParen) if in_declarator && self.broken_parameters => Separator::Newline,
+            (OpenBrace, _) | (_, CloseBrace) | (Semicolon, _) | (CloseBrace, _) => Separator::Newline,
+            (_, Semicolon | Comma | CloseParen) | (OpenParen, _) | (Identifier, OpenParen) => Separator::Nothing,
+            _ => Separator::Space,
+        }
+    }
+
+    // Whether the declarator and what follows it on its line stay within the line width.
+    fn declarator_fits(&self, declarator: &SyntaxNode) -> bool {
+        let tokens: Vec<SyntaxToken> = declarator.tokens().into_iter().filter(|token| !token.kind().is_trivia()).collect();
+        let mut width = self.depth * self.options.indent_width;
+
+        for (index, token) in tokens.iter().enumerate() {
+            if index > 0 && self.separator(&tokens[index - 1], token) == Separator::Space {
+                width += 1;
+            }
+
+            width += token.text().chars().count();
+        }
+
+        if self.options.brace_style == BraceStyle::SameLine {
+            width += " {".len();
+        }
+
+        width <= self.options.line_width
+    }
+
+    fn write_separator(&mut self, separator: Separator, indent: usize) {
+        match separator {
+            Separator::Nothing => {}
+            Separator::Space => self.output.push(' '),
+            Separator::Newline | Separator::BlankLine => {
+                // lines are never left with trailing blanks
+                self.output.truncate(self.output.trim_end_matches([' ', '\t']).len());
+                self.output.push('\n');
+
+                if separator == Separator::BlankLine {
+                    self.output.push('\n');
+                }
+
+                self.output.extend(std::iter::repeat_n(' ', indent));
+            }
+        }
+    }
+
+    fn write_token(&mut self, token: &SyntaxToken) {
+        match token.kind() {
+            TokenKind::Comment | TokenKind::Directive => self.output.push_str(token.text().trim_end()),
+            _ => self.output.push_str(token.text()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use super::*;
+
+    fn format_with(text: &str, options: &FormatOptions) -> String {
+        format(text, &Id::Main, options).unwrap_or_else(|errors| panic!("{:?}", errors))
+    }
+
+    fn format_default(text: &str) -> String {
+        format_with(text, &FormatOptions::default())
+    }
+
+    #[test]
+    fn reprints_in_house_style() {
+        assert_eq!(format_default("int   main ( void ){return 0x1F ;}"), "int main(void) {\n    return 0x1F;\n}\n");
+    }
+
+    #[test]
+    fn brace_style_and_indent_width() {
+        let options = FormatOptions { indent_width: 2, brace_style: BraceStyle::NextLine, ..FormatOptions::default() };
+
+        assert_eq!(format_with("int main(void) { return 0; }", &options), "int main(void)\n{\n  return 0;\n}\n");
+    }
+
+    #[test]
+    fn keeps_comments_and_directives() {
+        let text = "#include <stdio.h>\n\n\n// entry point\nint main( /* no arguments */ void) { /* nothing */\n\n  return 0; // done\n   }   \n";
+
+        assert_eq!(format_default(text), "\
+#include <stdio.h>
+
+// entry point
+int main(/* no arguments */ void) { /* nothing */
+    return 0; // done
+}
+");
+    }
+
+    #[test]
+    fn long_declarators_are_broken() {
+        let options = FormatOptions { line_width: 20, ..FormatOptions::default() };
+
+        assert_eq!(format_with("int a_long_name(void) { return 1; }", &options), "\
+int a_long_name(
+    void
+) {
+    return 1;
+}
+");
+    }
+
+    #[test]
+    fn refuses_code_with_errors() {
+        assert!(format("int main(void) { return @; }", &Id::Main, &FormatOptions::default()).is_err());
+        assert!(format("int main(void) { return 0 }", &Id::Main, &FormatOptions::default()).is_err());
+    }
+
+    #[test]
+    fn refuses_code_that_needs_preprocessing() {
+        for text in [
+            "#define ZERO 0\nint main(void) { return ZERO; }\n",
+            "int main(void) {\n#if 0\n    not C at all\n#endif\n    return 0;\n}\n",
+        ] {
+            let errors = format(text, &Id::Main, &FormatOptions::default()).unwrap_err();
+
+            assert!(errors[0].notes[0].contains("macros aren't expanded"));
+        }
+    }
+
+    fn significant(text: &str) -> Vec<String> {
+        Lexer::new(text).with_trivia(true).collect::<Vec<_>>().into_iter()
+            .filter(|token| token.kind != TokenKind::Whitespace)
+            .map(|token| text[token.span.range()].trim_end().to_string())
+            .collect()
+    }
+
+    proptest! {
+        // Any trivia between the tokens of a program formats to the same text, and formatting
+        // again changes nothing.
+        #[test]
+        fn formatting_is_idempotent(
+            gaps in prop::collection::vec(prop::sample::select(vec![
+                "", " ", "\n", "\n\n\n", "\t", " /* c */ ", "// line\n", "\n/* a\n   b */\n", "\n#define X 1\n",
+            ]), 11),
+            indent_width in 1usize..8,
+            line_width in 10usize..40,
+            next_line in any::<bool>(),
+        ) {
+            let words = ["int", "main", "(", "void", ")", "{", "return", "42", ";", "}"];
+            let mut text = gaps[0].to_string();
+
+            for (word, gap) in words.iter().zip(&gaps[1..]) {
+                text.push_str(word);
+                text.push_str(if gap.is_empty() && word.chars().all(char::is_alphanumeric) { " " } else { gap });
+            }
+
+            let brace_style = if next_line { BraceStyle::NextLine } else { BraceStyle::SameLine };
+            let options = FormatOptions { indent_width, brace_style, line_width };
+            let once = format_with(&text, &options);
+
+            prop_assert_eq!(format_with(&once, &options), once.clone());
+            prop_assert_eq!(significant(&once), significant(&text));
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::HashSet;
+use std::ops::Range;
+use std::str::FromStr;
+use lexer::lexer::Lexer;
+use lexer::tokens::TokenKind;
+use parser::parser::Parser;
+use parser::syntax::{NodeKind, SyntaxToken};
+use parser::token_collection::TokenCollection;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Class {
+    Keyword,
+    // `int` and `void`
+    Type,
+    // an identifier naming a function, where it is defined or used
+    Function,
+    // any other identifier
+    Local,
+    // numbers, characters and strings
+    Constant,
+    Comment,
+    Operator,
+    Directive,
+    // text the lexer couldn't make a token of
+    Error,
+    // whitespace
+    Plain,
+}
+
+impl Class {
+    // The CSS class in HTML output.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Class::Keyword => "keyword",
+            Class::Type => "type",
+            Class::Function => "function",
+            Class::Local => "local",
+            Class::Constant => "constant",
+            Class::Comment => "comment",
+            Class::Operator => "operator",
+            Class::Directive => "directive",
+            Class::Error => "error",
+            Class::Plain => "plain",
+        }
+    }
+
+    fn ansi(&self) -> Option<&'static str> {
+        Some(match self {
+            Class::Keyword => "\x1b[35m",
+            Class::Type => "\x1b[36m",
+            Class::Function => "\x1b[33m",
+            Class::Local => "\x1b[34m",
+            Class::Constant => "\x1b[32m",
+            Class::Comment => "\x1b[90m",
+            Class::Operator => "\x1b[1m",
+            Class::Directive => "\x1b[95m",
+            Class::Error => "\x1b[4;31m",
+            Class::Plain => return None,
+        })
+    }
+}
+
+// A run of the text and how it is shown.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Highlight {
+    pub class: Class,
+    pub range: Range<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum OutputFormat {
+    // `<span>`s with a CSS class each, in a `<pre>`
+    Html,
+    // colored for a terminal
+    Ansi,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "html" => Ok(OutputFormat::Html),
+            "ansi" => Ok(OutputFormat::Ansi),
+            _ => Err(format!("unknown output format '{}', expected 'html' or 'ansi'", s)),
+        }
+    }
+}
+
+// Classifies every token of `text`, as written and before preprocessing. The highlights
+// cover the whole text in order. Identifiers are told apart by what they resolve to: the
+// names of the functions the text defines, and anything called, are functions.
+pub fn classify(text: &str) -> Vec<Highlight> {
+    let (tokens, _) = Lexer::new(text).with_trivia(true).run();
+    let mut parser = Parser::new(TokenCollection::new(tokens), text);
+    parser.run();
+
+    let tokens = parser.syntax_tree().tokens();
+    let functions: HashSet<&str> = tokens.iter()
+        .filter(|token| token.kind() == TokenKind::Identifier && token.parent().kind() == NodeKind::Declarator)
+        .map(SyntaxToken::text)
+        .collect();
+
+    let significant: Vec<&SyntaxToken> = tokens.iter().filter(|token| !token.kind().is_trivia()).collect();
+    let called = |index: usize| significant.get(index + 1).is_some_and(|next| next.kind() == TokenKind::OpenParen);
+    let mut index = 0;
+
+    tokens.iter().map(|token| {
+        let class = match token.kind() {
+            TokenKind::Identifier if functions.contains(token.text()) || called(index) => Class::Function,
+            TokenKind::Identifier => Class::Local,
+            TokenKind::IntKeyword | TokenKind::VoidKeyword => Class::Type,
+            TokenKind::ReturnKeyword => Class::Keyword,
+            TokenKind::Constant | TokenKind::StringLiteral | TokenKind::CharConstant => Class::Constant,
+            TokenKind::Comment => Class::Comment,
+            TokenKind::Directive => Class::Directive,
+            TokenKind::Invalid => Class::Error,
+            TokenKind::Whitespace => Class::Plain,
+            _ => Class::Operator,
+        };
+
+        if !token.kind().is_trivia() {
+            index += 1;
+        }
+
+        Highlight { class, range: token.range() }
+    }).collect()
+}
+
+pub fn highlight(text: &str, format: OutputFormat) -> String {
+    let highlights = classify(text);
+
+    match format {
+        OutputFormat::Html => to_html(text, &highlights),
+        OutputFormat::Ansi => to_ansi(text, &highlights),
+    }
+}
+
+pub fn to_html(text: &str, highlights: &[Highlight]) -> String {
+    let mut html = String::from("<pre class=\"niamc\"><code>");
+
+    for highlight in highlights {
+        let escaped = escape(&text[highlight.range.clone()]);
+
+        match highlight.class {
+            Class::Plain => html.push_str(&escaped),
+            class => html.push_str(&format!("<span class=\"{}\">{}</span>", class.name(), escaped)),
+        }
+    }
+
+    html.push_str("</code></pre>\n");
+    html
+}
+
+pub fn to_ansi(text: &str, highlights: &[Highlight]) -> String {
+    let mut output = String::new();
+
+    for highlight in highlights {
+        let token = &text[highlight.range.clone()];
+
+        match highlight.class.ansi() {
+            Some(color) => output.push_str(&format!("{}{}\x1b[0m", color, token)),
+            None => output.push_str(token),
+        }
+    }
+
+    output
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classes(text: &str) -> Vec<(&str, Class)> {
+        classify(text).into_iter()
+            .filter(|highlight| highlight.class != Class::Plain)
+            .map(|highlight| (&text[highlight.range], highlight.class))
+            .collect()
+    }
+
+    #[test]
+    fn classifies_every_token() {
+        let text = "#include <a.h>\n// entry\nint main(void) { return helper(x, 'c') @ 0x1F; }\n";
+
+        assert_eq!(classes(text), vec![
+            ("#include <a.h>", Class::Directive),
+            ("// entry", Class::Comment),
+            ("int", Class::Type),
+            ("main", Class::Function),
+            ("(", Class::Operator),
+            ("void", Class::Type),
+            (")", Class::Operator),
+            ("{", Class::Operator),
+            ("return", Class::Keyword),
+            ("helper", Class::Function),
+            ("(", Class::Operator),
+            ("x", Class::Local),
+            (",", Class::Operator),
+            ("'c'", Class::Constant),
+            (")", Class::Operator),
+            ("@", Class::Error),
+            ("0x1F", Class::Constant),
+            (";", Class::Operator),
+            ("}", Class::Operator),
+        ]);
+
+        let covered: String = classify(text).iter().map(|highlight| &text[highlight.range.clone()]).collect();
+        assert_eq!(covered, text);
+    }
+
+    #[test]
+    fn functions_are_resolved_by_name() {
+        // `main` is a function wherever it appears, called or not
+        assert_eq!(classes("int main(void) { return main; }")[7], ("main", Class::Function));
+    }
+
+    #[test]
+    fn html_output() {
+        assert_eq!(highlight("int a<b; /* & */", OutputFormat::Html), "<pre class=\"niamc\"><code>\
+<span class=\"type\">int</span> <span class=\"local\">a</span><span class=\"operator\">&lt;</span>\
+<span class=\"local\">b</span><span class=\"operator\">;</span> <span class=\"comment\">/* &amp; */</span>\
+</code></pre>\n");
+    }
+
+    #[test]
+    fn ansi_output() {
+        assert_eq!(highlight("return 1;", OutputFormat::Ansi), "\x1b[35mreturn\x1b[0m \x1b[32m1\x1b[0m\x1b[1m;\x1b[0m");
+    }
+}
@@ -0,0 +1,67 @@
+use std::fmt::Write;
+use std::hint::black_box;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use common::source_file::TextEdit;
+use lexer::lexer::Lexer;
+
+// A C file of `functions` small functions, mixing every kind of token and comment.
+fn generate(functions: usize) -> String {
+    let mut text = String::from("/* generated for benchmarking */\n");
+
+    for i in 0..functions {
+        writeln!(text, "// function {}", i).unwrap();
+        writeln!(text, "int function_{}(int argument, int *pointer) {{", i).unwrap();
+        writeln!(text, "    int value = argument << {} | 0x{:X}u; /* mask */", i % 31, i).unwrap();
+        writeln!(text, "    value += pointer[{}] ? -value : ~value;", i % 7).unwrap();
+        writeln!(text, "    if (value >= {} && value != argument) {{ value <<= 1; }}", i).unwrap();
+        writeln!(text, "    return value;\n}}\n").unwrap();
+    }
+
+    text
+}
+
+fn lex(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lexer");
+
+    for functions in [100, 1_000, 10_000] {
+        let text = generate(functions);
+        group.throughput(Throughput::Bytes(text.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(text.len()), &text, |b, text| {
+            b.iter(|| Lexer::new(black_box(text)).run())
+        });
+    }
+
+    group.finish();
+}
+
+fn identifiers(c: &mut Criterion) {
+    let text = "identifier_with_a_long_name return int void ".repeat(20_000);
+    let mut group = c.benchmark_group("lexer-identifiers");
+
+    group.throughput(Throughput::Bytes(text.len() as u64));
+    group.bench_function("keywords-and-identifiers", |b| b.iter(|| Lexer::new(black_box(&text)).run()));
+    group.finish();
+}
+
+// One keystroke in the middle of a large file, relexed against lexing it all again.
+fn relex(c: &mut Criterion) {
+    let text = generate(10_000);
+    let middle = text.find("function_5000(").unwrap();
+    let edit = TextEdit::new(middle..middle, "x");
+    let mut edited = text.clone();
+    edited.replace_range(edit.range.clone(), &edit.replacement);
+
+    let (old, _) = Lexer::new(&text).with_trivia(true).run();
+    let mut group = c.benchmark_group("lexer-edit");
+
+    group.bench_function("relex", |b| b.iter_batched_ref(
+        || old.clone(),
+        |tokens| Lexer::new(black_box(&edited)).with_trivia(true).relex(tokens, &edit),
+        BatchSize::LargeInput,
+    ));
+    group.bench_function("lex-again", |b| b.iter(|| Lexer::new(black_box(&edited)).with_trivia(true).run()));
+    group.finish();
+}
+
+criterion_group!(benches, lex, identifiers, relex);
+criterion_main!(benches);
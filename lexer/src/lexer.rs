@@ -1,50 +1,98 @@
+use std::ops::Range;
 use ariadne::Span as ASpan;
-use std::collections::HashMap;
-use regex::Regex;
+use common::diagnostics::Diagnostic;
+use common::error_codes::ErrorCode;
+use common::identifiers::{is_identifier_continue, is_identifier_start, universal_character_name};
+use common::source_file::{Id, TextEdit};
 use common::span::Span;
-use crate::tokens::{char_tokens, identifiers_or_constant, keywords, multiline_comment_start, multiline_comment_start_or_end, newline, single_line_comment_start, whitespace, Token, TokenKind};
+use common::warnings::Warning;
+use crate::tokens::{keyword, punctuator, Token, TokenKind};
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub enum LexerError {
     UnknownToken(Span),
-    NestedComment(Span, Span), // start, second start found
-    UnexpectedEofInsideComment(Span) // where started
+    NestedComment(Span, Span), // start, second start found; a warning, as this is valid C
+    UnexpectedEofInsideComment(Span), // where started
+    UnterminatedLiteral(Span), // from the opening quote to the end of the line
 }
 
+impl LexerError {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        match self {
+            LexerError::UnexpectedEofInsideComment(span) =>
+                Diagnostic::error(span.clone(), "Comment started but not finished before end of file.")
+                    .with_code(ErrorCode::UnterminatedComment)
+                    .with_label(span.clone(), "Comment starts here"),
+            LexerError::NestedComment(comment_start, nested_comment_start) =>
+                Diagnostic::warning(Warning::Comment, nested_comment_start.clone(), "'/*' within block comment")
+                    .with_code(ErrorCode::NestedComment)
+                    .with_label(comment_start.clone(), "Comment starts here")
+                    .with_label(nested_comment_start.clone(), "Does not start a nested comment")
+                    .with_note("the comment ends at the first '*/'; use --nested-comments to let comments nest"),
+            LexerError::UnterminatedLiteral(span) =>
+                Diagnostic::error(span.clone(), "Missing closing quote.")
+                    .with_code(ErrorCode::UnterminatedLiteral)
+                    .with_label(span.clone(), "Literal starts here"),
+            LexerError::UnknownToken(span) =>
+                Diagnostic::error(span.clone(), "Unknown token.")
+                    .with_code(ErrorCode::UnknownToken)
+                    .with_label(span.clone(), "Unknown token"),
+        }
+    }
+}
+
+// How far past the end of a token the lexer may have looked to decide where it ends: the
+// ten bytes of a `\\U` universal character name that would have continued an identifier.
+const LOOKAHEAD: usize = 10;
+
 pub struct Lexer<'a> {
     pub text: &'a str,
+    bytes: &'a [u8],
+    unit: Id,
     position: usize,
-    total_length: usize,
-    stop: bool,
+    // whether `/*` inside a block comment opens a nested one, instead of standard C
+    nested_comments: bool,
+    // whether whitespace, comments and bytes that form no token come out as tokens too
+    trivia: bool,
     pub errors: Vec<LexerError>,
-    whitespace: Regex,
-    keywords: HashMap<&'static str, TokenKind>,
-    id_const: Regex,
-    multiline_comment_start: Regex,
-    single_line_comment_start: Regex,
-    multiline_comment_start_or_end: Regex,
-    multiline_comment_start_str: &'a str,
-    newline: Regex,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(text: &'_ str) -> Lexer<'_> {
+        Lexer::new_with_unit(text, Id::Main)
+    }
+
+    // Tokens and errors get spans in `unit`, so diagnostics point into the right file.
+    pub fn new_with_unit(text: &'_ str, unit: Id) -> Lexer<'_> {
         Lexer { text,
+            bytes: text.as_bytes(),
+            unit,
             position: 0,
-            total_length: text.len(),
-            stop: false,
+            nested_comments: false,
+            trivia: false,
             errors: Vec::new(),
-            whitespace: whitespace(),
-            keywords: keywords(),
-            id_const: identifiers_or_constant(),
-            multiline_comment_start: multiline_comment_start(),
-            multiline_comment_start_or_end: multiline_comment_start_or_end(),
-            single_line_comment_start: single_line_comment_start(),
-            multiline_comment_start_str: "/*",
-            newline: newline(),
         }
     }
 
+    pub fn with_nested_comments(mut self, enabled: bool) -> Lexer<'a> {
+        self.nested_comments = enabled;
+        self
+    }
+
+    // With trivia the tokens cover every byte of the text, so it can be rebuilt from them.
+    pub fn with_trivia(mut self, enabled: bool) -> Lexer<'a> {
+        self.trivia = enabled;
+        self
+    }
+
+    fn span(&self, start: usize, len: usize) -> Span {
+        Span::new_with_unit(start, len, self.unit.clone())
+    }
+
+    fn peek(&self, offset: usize) -> Option<u8> {
+        self.bytes.get(self.position + offset).copied()
+    }
+
     // Appends errors to previous if contiguous
     pub fn add_error_span(&mut self, error: Span) {
         if let Some(LexerError::UnknownToken(span)) = self.errors.last_mut() && span.end() == error.start() {
@@ -67,88 +115,251 @@ impl<'a> Lexer<'a> {
     pub fn get_text(&self, span: &Span) -> &'a str {
         &self.text[span.range()]
     }
-}
 
-impl<'a> Iterator for Lexer<'a> {
-    type Item = Token;
+    // Brings `tokens`, lexed from the text before `edit`, up to date with this lexer's text,
+    // which has the edit applied. Lexing starts over only near the edit and stops once the new
+    // tokens line up with the old ones again, giving the same tokens as lexing the whole text.
+    // Returns the indices of the tokens that were lexed again.
+    pub fn relex(mut self, tokens: &mut Vec<Token>, edit: &TextEdit) -> Range<usize> {
+        // tokens that end well before the edit stay as they are, or before its logical line when
+        // that starts on an earlier line, as a literal can go on past an escaped line break
+        let mut start = edit.range.start;
+        let escaped = |end: usize| self.bytes[..end].ends_with(b"\\") || self.bytes[..end].ends_with(b"\\\r");
+
+        while let Some(newline) = self.bytes[..start].iter().rposition(|&c| c == b'\n') && escaped(newline) {
+            start = newline;
+        }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            if self.position == self.total_length || self.stop {
-                break None
-            } else {
-                // check whitespace
-                if let Some(ws) = self.whitespace.find(&self.text[self.position..]) {
-                    self.position += ws.len();
-                    continue;
+        let first = tokens.partition_point(|token| token.span.end() + LOOKAHEAD < start);
+        let mut relexed = Vec::new();
+        self.position = first.checked_sub(1).map_or(0, |previous| tokens[previous].span.end());
+
+        let next = loop {
+            // After a line break behind the edit the text and where the lexer stands in it are
+            // the same as before, line starts included, so the old tokens from there still hold.
+            if self.position >= edit.new_end() && self.text[edit.new_end()..self.position].contains('\n') {
+                let position = edit.unshift(self.position);
+                let next = tokens.partition_point(|token| token.span.start() < position);
+
+                // the old lexer stood there too only if an old token ends there; with none
+                // before it, a comment may have swallowed the text up to it
+                if next > 0 && tokens[next - 1].span.end() == position {
+                    break next;
                 }
+            }
+
+            match self.next() {
+                Some(token) => relexed.push(token),
+                None => break tokens.len(),
+            }
+        };
+
+        for token in &mut tokens[next..] {
+            token.span.move_to(edit.shift(token.span.start()));
+        }
 
-                if self.position < self.total_length - 2 && let Some(comment_start) = self.multiline_comment_start.find(&self.text[self.position..self.position + 2]) {
-                    if let Some(maybe_nested) = self.multiline_comment_start_or_end.find(&self.text[comment_start.end()..]) {
-                        // a comment start found within still a comment
-                        if maybe_nested.as_str() == self.multiline_comment_start_str {
-                            self.errors.push(LexerError::NestedComment(Span::from(comment_start.range()), Span::from(maybe_nested.range())));
-                            self.stop = true;
-                            break None;
-                        } else {
-                            self.position = maybe_nested.end() + comment_start.end();
-                            continue;
-                        }
-                    } else {
-                        self.errors.push(LexerError::UnexpectedEofInsideComment(Span::from(comment_start.range())));
-                        self.stop = true;
-                        break None;
+        let end = first + relexed.len();
+        tokens.splice(first..next, relexed);
+        first..end
+    }
+
+    // Skips a `/* */` comment starting at the current position, recording an error if it is
+    // never closed and a warning for a `/*` inside it in standard mode.
+    fn block_comment(&mut self) {
+        let start = self.position;
+        let mut position = start + 2;
+        let mut depth = 1;
+        let mut warned = false;
+
+        while position + 1 < self.bytes.len() {
+            match (self.bytes[position], self.bytes[position + 1]) {
+                (b'*', b'/') => {
+                    position += 2;
+                    depth -= 1;
+
+                    if depth == 0 {
+                        self.position = position;
+                        return;
                     }
-                } // no multiline comment found
-
-                if let Some(comment_start) = self.single_line_comment_start.find(&self.text[self.position..]) {
-                    if let Some(eol) = self.newline.find_at(&self.text[self.position..], comment_start.end()) {
-                        self.position = self.position + eol.end();
-                        continue;
-                    } else {
-                        self.position = self.total_length;
-                        self.stop = true;
-                        break None;
+                }
+                (b'/', b'*') if self.nested_comments => {
+                    position += 2;
+                    depth += 1;
+                }
+                (b'/', b'*') => {
+                    if !warned {
+                        self.errors.push(LexerError::NestedComment(self.span(start, 2), self.span(position, 2)));
+                        warned = true;
                     }
+                    // only one byte, as in `/* /*/` the `*` also closes the comment
+                    position += 1;
                 }
+                _ => position += 1,
+            }
+        }
+
+        self.errors.push(LexerError::UnexpectedEofInsideComment(self.span(start, 2)));
+        self.position = self.bytes.len();
+    }
+
+    // Whether only blanks stand between the start of the line and the current position.
+    fn at_line_start(&self) -> bool {
+        let blanks = self.bytes[..self.position].iter().rev().take_while(|&&c| c == b' ' || c == b'\t').count();
+        self.position == blanks || self.bytes[self.position - blanks - 1] == b'\n'
+    }
+
+    // Skips a preprocessing directive, up to the line break that is not escaped by a backslash.
+    fn directive(&mut self) {
+        while let Some(c) = self.peek(0) {
+            match c {
+                b'\n' => break,
+                b'\\' if self.peek(1) == Some(b'\n') => self.position += 2,
+                b'\\' if self.peek(1) == Some(b'\r') && self.peek(2) == Some(b'\n') => self.position += 3,
+                _ => self.position += 1,
+            }
+        }
+    }
+
+    // Skips a `//` comment, up to but not including the line break.
+    fn line_comment(&mut self) {
+        self.position = match self.bytes[self.position..].iter().position(|&c| c == b'\n') {
+            Some(newline) => self.position + newline,
+            None => self.bytes.len(),
+        };
+    }
+
+    fn identifier(&mut self) -> Option<Token> {
+        let start = self.position;
 
-                // check single length symbols
-                if let Some(kind) = char_tokens(&self.text[self.position..]) {
-                    self.position += 1;
-                    break Some(Token { kind, span: Span::new(self.position-1, 1) });
+        loop {
+            if self.peek(0).is_some_and(is_identifier_continue) {
+                self.position += 1;
+            } else if let Some(len) = universal_character_name(&self.bytes[self.position..]) {
+                self.position += len;
+            } else {
+                break;
+            }
+        }
+
+        let text = &self.text[start..self.position];
+
+        if matches!(text, "L" | "u" | "U" | "u8") && let Some(quote @ (b'"' | b'\'')) = self.peek(0) {
+            return self.literal(start, quote);
+        }
+
+        let kind = keyword(text).unwrap_or(TokenKind::Identifier);
+
+        Some(Token { kind, span: self.span(start, self.position - start) })
+    }
+
+    // A string literal or character constant whose opening quote is at the current position
+    // and whose prefix, if any, starts at `start`. Without a closing quote on the line the
+    // rest of the line is reported and skipped.
+    fn literal(&mut self, start: usize, quote: u8) -> Option<Token> {
+        let kind = if quote == b'"' { TokenKind::StringLiteral } else { TokenKind::CharConstant };
+        let mut position = self.position + 1;
+
+        while let Some(&c) = self.bytes.get(position) {
+            match c {
+                b'\\' => position += 1 + self.text[position + 1..].chars().next().map_or(0, char::len_utf8),
+                b'\n' => break,
+                _ if c == quote => {
+                    self.position = position + 1;
+                    return Some(Token { kind, span: self.span(start, self.position - start) });
                 }
+                _ => position += 1,
+            }
+        }
 
-                if let Some(caps) = self.id_const.captures(&self.text[self.position..]) {
-                    // this value must be set as it can't determine on its own that it has been set below
-                    let mut span = Span::new(self.position, 1);
-                    // this will get overwritten
-                    let mut kind = TokenKind::Invalid;
-
-                    if let Some(identifier) = caps.get(1) {
-                        span = Span::new(self.position, identifier.len());
-
-                        if let Some(k) = self.keywords.get(&self.get_text(&span)) {
-                            kind = *k;
-                        } else {
-                            kind = TokenKind::Identifier;
-                        }
-                    } else if let Some(constant) = caps.get(2) {
-                        span = Span::new(self.position, constant.len());
-                        kind = TokenKind::Constant;
-                    }
+        let end = self.bytes[start..].iter().position(|&c| c == b'\n').map_or(self.bytes.len(), |end| start + end);
+        self.errors.push(LexerError::UnterminatedLiteral(self.span(start, end - start)));
+        self.position = end;
+        None
+    }
+
+    // Takes a whole preprocessing number, so suffixes and malformed digits stay part of the
+    // constant and are reported when its value is parsed.
+    fn number(&mut self) -> Token {
+        let start = self.position;
+        self.position += 1;
+
+        while let Some(c) = self.peek(0) {
+            if matches!(c, b'e' | b'E' | b'p' | b'P') && matches!(self.peek(1), Some(b'+' | b'-')) {
+                self.position += 2;
+            } else if is_identifier_continue(c) || c == b'.' {
+                self.position += 1;
+            } else if c == b'\'' && self.peek(1).is_some_and(is_identifier_continue) {
+                // C23 digit separator
+                self.position += 2;
+            } else {
+                break;
+            }
+        }
 
-                    self.position += span.len();
+        Token { kind: TokenKind::Constant, span: self.span(start, self.position - start) }
+    }
+}
 
-                    break Some(Token { kind, span });
-                } else {
-                    // if we don't have a match add it to the error list
-                    let span = Span::new(self.position, 1);
-                    self.position += 1;
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token;
 
-                    self.add_error_span(span);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let start = self.position;
+            let c = self.peek(0)?;
+
+            // what was skipped, for when trivia is wanted
+            let kind = match c {
+                b' ' | b'\t' | b'\n' | b'\r' | b'\x0b' | b'\x0c' => {
+                    while self.peek(0).is_some_and(|c| matches!(c, b' ' | b'\t' | b'\n' | b'\r' | b'\x0b' | b'\x0c')) {
+                        self.position += 1;
+                    }
 
-                    continue;
+                    TokenKind::Whitespace
+                }
+                b'/' if self.peek(1) == Some(b'*') => {
+                    self.block_comment();
+                    TokenKind::Comment
+                }
+                b'/' if self.peek(1) == Some(b'/') => {
+                    self.line_comment();
+                    TokenKind::Comment
                 }
+                _ if is_identifier_start(c) || universal_character_name(&self.bytes[self.position..]).is_some() =>
+                    match self.identifier() {
+                        Some(token) => return Some(token),
+                        None => TokenKind::Invalid,
+                    },
+                b'"' | b'\'' => match self.literal(self.position, c) {
+                    Some(token) => return Some(token),
+                    None => TokenKind::Invalid,
+                },
+                // the source has not been preprocessed when trivia is wanted
+                b'#' | b'%' if self.trivia && (c == b'#' || self.peek(1) == Some(b':')) && self.at_line_start() => {
+                    self.directive();
+                    TokenKind::Directive
+                }
+                b'0'..=b'9' => return Some(self.number()),
+                b'.' if self.peek(1).is_some_and(|c| c.is_ascii_digit()) => return Some(self.number()),
+                _ => match punctuator(&self.bytes[self.position..]) {
+                    Some((kind, len)) => {
+                        self.position += len;
+                        return Some(Token { kind, span: self.span(self.position - len, len) });
+                    }
+                    None => {
+                        // a character outside the basic source set, taken whole
+                        let len = self.text[self.position..].chars().next().map_or(1, char::len_utf8);
+                        let span = self.span(self.position, len);
+                        self.position += len;
+
+                        self.add_error_span(span);
+                        TokenKind::Invalid
+                    }
+                },
+            };
+
+            if self.trivia {
+                return Some(Token { kind, span: self.span(start, self.position - start) });
             }
         }
     }
@@ -159,6 +370,9 @@ impl<'a> Iterator for Lexer<'a> {
 mod tests {
     use std::ops::Range;
     use assert_matches::assert_matches;
+    use common::diagnostics::Severity;
+    use common::source_file::SourceFile;
+    use proptest::prelude::*;
     use crate::tokens::TokenKind;
     use super::*;
 
@@ -188,6 +402,22 @@ mod tests {
         assert_eq!(results[1].span.range(), 3..5);
     }
 
+    #[test]
+    fn universal_character_names() {
+        let mut lexer = Lexer::new("caf\\u00e9 \\U0001F600 \\u0041");
+
+        let results = lexer.collect_tokens();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].kind, TokenKind::Identifier);
+        assert_eq!(results[0].span.range(), 0..9);
+        assert_eq!(results[1].kind, TokenKind::Identifier);
+        assert_eq!(results[1].span.range(), 10..20);
+        // `A` has to be written as itself, so this is a stray backslash
+        assert_eq!(lexer.errors.len(), 1);
+        assert_eq!(results[2].span.range(), 22..27);
+    }
+
     #[test]
     fn at_sign_error() {
         let mut lexer = Lexer::new("return 0@1;");
@@ -203,7 +433,7 @@ mod tests {
         assert_eq!(results[2].kind, TokenKind::Constant);
         assert_eq!(results[2].span.range(), 9..10);
         assert_eq!(results[3].kind, TokenKind::Semicolon);
-        assert_eq!(results[3].span.range(), 11..12);
+        assert_eq!(results[3].span.range(), 10..11);
     }
 
     #[test]
@@ -281,9 +511,9 @@ mod tests {
         assert_eq!(results[2].kind, TokenKind::OpenBrace);
         assert_eq!(results[2].span.range(), 2..3);
         assert_eq!(results[3].kind, TokenKind::CloseBrace);
-        assert_eq!(results[3].span.range(), 4..5);
+        assert_eq!(results[3].span.range(), 3..4);
         assert_eq!(results[4].kind, TokenKind::Semicolon);
-        assert_eq!(results[4].span.range(), 5..6);
+        assert_eq!(results[4].span.range(), 4..5);
     }
 
     #[test]
@@ -391,6 +621,253 @@ main(void) {
         assert_matches!(&lexer.errors[..], []);
     }
 
+    #[test]
+    fn spans_carry_the_unit() {
+        let unit = Id::Unit("second.c".to_string());
+        let mut lexer = Lexer::new_with_unit("int @", unit.clone());
+
+        let results = lexer.collect_tokens();
+
+        assert_eq!(results[0].span.source(), &unit);
+        assert_matches!(&lexer.errors[..], [LexerError::UnknownToken(span)] if span.source() == &unit);
+    }
+
+    #[test]
+    fn punctuators_use_maximal_munch() {
+        let mut lexer = Lexer::new("a<<=b->c...d+++e%:%:<::>");
+
+        let kinds: Vec<TokenKind> = lexer.collect_tokens().into_iter().map(|token| token.kind).collect();
+
+        assert_eq!(kinds, vec![
+            TokenKind::Identifier, TokenKind::ShiftLeftEqual, TokenKind::Identifier, TokenKind::Arrow,
+            TokenKind::Identifier, TokenKind::Ellipsis, TokenKind::Identifier, TokenKind::PlusPlus,
+            TokenKind::Plus, TokenKind::Identifier, TokenKind::HashHash, TokenKind::OpenBracket,
+            TokenKind::CloseBracket,
+        ]);
+        assert_matches!(&lexer.errors[..], []);
+    }
+
+    #[test]
+    fn numbers_are_taken_whole() {
+        let mut lexer = Lexer::new("0x1Fu 1'000 1e+5 .5");
+
+        let results = lexer.collect_tokens();
+
+        assert_eq!(results.len(), 4);
+        test_token(&results[0], TokenKind::Constant, 0..5);
+        test_token(&results[1], TokenKind::Constant, 6..11);
+        test_token(&results[2], TokenKind::Constant, 12..16);
+        test_token(&results[3], TokenKind::Constant, 17..19);
+    }
+
+    #[test]
+    fn comment_errors_point_into_the_text() {
+        let mut lexer = Lexer::new("int x; /* a /* b */");
+
+        lexer.collect_tokens();
+
+        assert_matches!(&lexer.errors[..], [LexerError::NestedComment(start, nested)] if start.range() == (7..9) && nested.range() == (12..14));
+    }
+
+    #[test]
+    fn lexing_continues_after_a_nested_comment_start() {
+        let mut lexer = Lexer::new("/* a /* b */ int /* c /*/ return");
+
+        let results = lexer.collect_tokens();
+
+        assert_matches!(&results[..], [Token { kind: TokenKind::IntKeyword, .. }, Token { kind: TokenKind::ReturnKeyword, .. }]);
+        assert_matches!(&lexer.errors[..], [LexerError::NestedComment(..), LexerError::NestedComment(..)]);
+        assert_eq!(lexer.errors[0].to_diagnostic().severity, Severity::Warning);
+    }
+
+    #[test]
+    fn nested_comments_mode() {
+        let mut lexer = Lexer::new("/* a /* b */ c */ int /* /* */").with_nested_comments(true);
+
+        let results = lexer.collect_tokens();
+
+        assert_matches!(&results[..], [Token { kind: TokenKind::IntKeyword, span }] if span.range() == (18..21));
+        assert_matches!(&lexer.errors[..], [LexerError::UnexpectedEofInsideComment(span)] if span.range() == (22..24));
+    }
+
+    #[test]
+    fn errors_after_an_unterminated_comment_are_kept() {
+        let mut lexer = Lexer::new("@ /* never closed");
+
+        lexer.collect_tokens();
+
+        assert_matches!(&lexer.errors[..], [LexerError::UnknownToken(_), LexerError::UnexpectedEofInsideComment(_)]);
+    }
+
+    #[test]
+    fn literals_may_hold_any_text() {
+        let mut lexer = Lexer::new("\"héllo \\\" wörld\" L'ß' u8\"\"");
+
+        let results = lexer.collect_tokens();
+
+        assert_eq!(results.len(), 3);
+        test_token(&results[0], TokenKind::StringLiteral, 0..18);
+        test_token(&results[1], TokenKind::CharConstant, 19..24);
+        test_token(&results[2], TokenKind::StringLiteral, 25..29);
+        assert_matches!(&lexer.errors[..], []);
+    }
+
+    #[test]
+    fn unterminated_literal_skips_the_line() {
+        let mut lexer = Lexer::new("return 'ä;\n0;");
+
+        let results = lexer.collect_tokens();
+
+        assert_matches!(&results[..], [
+            Token { kind: TokenKind::ReturnKeyword, .. },
+            Token { kind: TokenKind::Constant, .. },
+            Token { kind: TokenKind::Semicolon, .. },
+        ]);
+        assert_matches!(&lexer.errors[..], [LexerError::UnterminatedLiteral(span)] if span.range() == (7..11));
+    }
+
+    proptest! {
+        #[test]
+        fn any_text_lexes_without_panicking(text in "\\PC*") {
+            check_spans(&text);
+        }
+
+        #[test]
+        fn relexing_matches_lexing_again(
+            text in "([a-z0-9_ \n\"'/*#\\\\.<>=+-]|é)*",
+            replacement in "([a-z0-9_ \n\"'/*#\\\\.<>=+-]|é){0,4}",
+            start in any::<prop::sample::Index>(),
+            len in 0usize..6,
+            trivia in any::<bool>(),
+        ) {
+            let boundaries: Vec<usize> = text.char_indices().map(|(index, _)| index).chain([text.len()]).collect();
+            let start = start.index(boundaries.len());
+            let end = boundaries[(start + len).min(boundaries.len() - 1)];
+
+            check_relex(&text, &TextEdit::new(boundaries[start]..end, replacement), trivia);
+        }
+
+        #[test]
+        fn c_like_text_lexes_without_panicking(text in "([a-z0-9_ \n\"'/*\\\\.<>=+-]|é|ß|😀)*") {
+            check_spans(&text);
+        }
+    }
+
+    // Every span lies on character boundaries, tokens come in order, every error renders, and
+    // with trivia the tokens spell out the whole text.
+    fn check_spans(text: &str) {
+        let mut lexer = Lexer::new(text);
+        let (tokens, errors) = lexer.run();
+        let mut end = 0;
+
+        for token in &tokens {
+            assert!(token.span.start() >= end);
+            assert!(text.is_char_boundary(token.span.start()) && text.is_char_boundary(token.span.end()));
+            end = token.span.end();
+        }
+
+        let (with_trivia, trivia_errors) = Lexer::new(text).with_trivia(true).run();
+        let rebuilt: String = with_trivia.iter().map(|token| &text[token.span.range()]).collect();
+
+        // directive lines are a single trivia token then, whatever they contain
+        let directives: Vec<_> = with_trivia.iter().filter(|token| token.kind == TokenKind::Directive).map(|token| token.span.range()).collect();
+        let outside = |span: &Span| !directives.iter().any(|directive| directive.contains(&span.start()));
+
+        assert_eq!(rebuilt, text);
+        assert_eq!(trivia_errors, errors.into_iter().filter(|error| outside(&error.to_diagnostic().span)).collect::<Vec<_>>());
+        assert_eq!(with_trivia.into_iter().filter(|token| !token.kind.is_trivia()).collect::<Vec<_>>(),
+            tokens.into_iter().filter(|token| outside(&token.span)).collect::<Vec<_>>());
+
+        let file = SourceFile::from_string(Id::Main, text);
+
+        for error in &lexer.errors {
+            let diagnostic = error.to_diagnostic();
+            assert!(text.is_char_boundary(diagnostic.span.start()) && text.is_char_boundary(diagnostic.span.end()));
+            diagnostic.report().write(&file, &mut Vec::new()).unwrap();
+        }
+    }
+
+    // Relexing after `edit` gives what lexing the edited text from scratch does.
+    fn check_relex(text: &str, edit: &TextEdit, trivia: bool) -> Range<usize> {
+        let mut edited = text.to_string();
+        edited.replace_range(edit.range.clone(), &edit.replacement);
+
+        let (mut tokens, _) = Lexer::new(text).with_trivia(trivia).run();
+        let relexed = Lexer::new(&edited).with_trivia(trivia).relex(&mut tokens, edit);
+
+        assert_eq!(tokens, Lexer::new(&edited).with_trivia(trivia).run().0);
+        relexed
+    }
+
+    #[test]
+    fn relexing_past_a_removed_comment() {
+        // there are no old tokens to line up with where the comment was
+        check_relex("/*\n<a", &TextEdit::new(0..1, ""), false);
+    }
+
+    #[test]
+    fn relexing_starts_at_the_logical_line() {
+        // closing the literal makes it go on from the line before
+        let text = "\"\\\n-aaéééé\\";
+        check_relex(text, &TextEdit::new(text.len()..text.len(), "a\""), false);
+    }
+
+    #[test]
+    fn relexing_stops_at_the_next_line() {
+        let text = "int main(void) {\n    return 1;\n}\n\nint other(void) {\n    return 2;\n}\n";
+        let one = text.find('1').unwrap();
+
+        assert_eq!(check_relex(text, &TextEdit::new(one..one + 1, "0x10"), true), 8..14);
+        assert_eq!(check_relex(text, &TextEdit::new(one..one + 1, "0x10"), false), 6..10);
+        // an opened comment swallows everything up to where it is closed
+        assert_eq!(check_relex(text, &TextEdit::new(one..one, "/*"), true), 8..12);
+        // a directive is one only at the start of its line
+        check_relex("x;\n  #define A\n", &TextEdit::new(0..3, ""), true);
+        check_relex("  #define A\n", &TextEdit::new(0..0, "x"), true);
+        // tokens before the edit can change too
+        check_relex("a.. b\n", &TextEdit::new(3..3, "."), false);
+        check_relex("x\\u00e b\n", &TextEdit::new(7..7, "9"), true);
+    }
+
+    #[test]
+    fn trivia_tokens() {
+        let text = "int /* a */ x; // b\n@\"c";
+        let tokens = Lexer::new(text).with_trivia(true).collect_tokens();
+        let kinds: Vec<_> = tokens.iter().map(|token| token.kind).collect();
+
+        assert_eq!(kinds, vec![
+            TokenKind::IntKeyword, TokenKind::Whitespace, TokenKind::Comment, TokenKind::Whitespace,
+            TokenKind::Identifier, TokenKind::Semicolon, TokenKind::Whitespace, TokenKind::Comment,
+            TokenKind::Whitespace, TokenKind::Invalid, TokenKind::Invalid,
+        ]);
+        assert_eq!(tokens[7].span.range(), 15..19);
+        assert_eq!(tokens[10].span.range(), 21..23);
+    }
+
+    #[test]
+    fn directives_are_trivia() {
+        let text = "  #define A \\\n  @ 1\nA # x\n%:include <a>";
+        let tokens = Lexer::new(text).with_trivia(true).collect_tokens();
+        let kinds: Vec<_> = tokens.iter().map(|token| token.kind).collect();
+
+        assert_eq!(kinds, vec![
+            TokenKind::Whitespace, TokenKind::Directive, TokenKind::Whitespace, TokenKind::Identifier,
+            TokenKind::Whitespace, TokenKind::Hash, TokenKind::Whitespace, TokenKind::Identifier,
+            TokenKind::Whitespace, TokenKind::Directive,
+        ]);
+        assert_eq!(tokens[1].span.range(), 2..19);
+    }
+
+    #[test]
+    fn unknown_characters_are_taken_whole() {
+        let mut lexer = Lexer::new("a é$ b");
+
+        let results = lexer.collect_tokens();
+
+        assert_eq!(results.len(), 2);
+        assert_matches!(&lexer.errors[..], [LexerError::UnknownToken(span)] if span.range() == (2..5));
+    }
+
     fn test_token(result: &Token, expected_kind: TokenKind, expected_range: Range<usize>) {
         assert_eq!(result.kind, expected_kind);
         assert_eq!(result.span.range(), expected_range);
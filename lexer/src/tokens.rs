@@ -1,49 +1,221 @@
-use std::collections::HashMap;
-use std::fmt::{Debug, Display};
-use regex::{Match, Regex};
+use std::fmt::Display;
 use common::span::Span;
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum TokenKind {
     Identifier,
     Constant,
+    StringLiteral,
+    CharConstant,
     IntKeyword,
     VoidKeyword,
     ReturnKeyword,
+    OpenBracket,
+    CloseBracket,
     OpenParen,
     CloseParen,
     OpenBrace,
     CloseBrace,
+    Dot,
+    Arrow,
+    PlusPlus,
+    MinusMinus,
+    Ampersand,
+    Star,
+    Plus,
+    Minus,
+    Tilde,
+    Bang,
+    Slash,
+    Percent,
+    ShiftLeft,
+    ShiftRight,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    EqualEqual,
+    NotEqual,
+    Caret,
+    Pipe,
+    AndAnd,
+    OrOr,
+    Question,
+    Colon,
     Semicolon,
+    Ellipsis,
+    Equal,
+    StarEqual,
+    SlashEqual,
+    PercentEqual,
+    PlusEqual,
+    MinusEqual,
+    ShiftLeftEqual,
+    ShiftRightEqual,
+    AmpersandEqual,
+    CaretEqual,
+    PipeEqual,
+    Comma,
+    Hash,
+    HashHash,
     Whitespace,
+    Comment,
+    // a whole preprocessing directive line, in source that has not been preprocessed
+    Directive,
+    // text the lexer reported an error for and made no token of
     Invalid,
 }
 
-impl Display for TokenKind {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        Debug::fmt(self, f)
+impl TokenKind {
+    // How the token is named in messages: punctuators and keywords are quoted as written.
+    pub fn spelling(&self) -> &'static str {
+        match self {
+            TokenKind::Identifier => "identifier",
+            TokenKind::Constant => "constant",
+            TokenKind::StringLiteral => "string literal",
+            TokenKind::CharConstant => "character constant",
+            TokenKind::IntKeyword => "'int'",
+            TokenKind::VoidKeyword => "'void'",
+            TokenKind::ReturnKeyword => "'return'",
+            TokenKind::OpenBracket => "'['",
+            TokenKind::CloseBracket => "']'",
+            TokenKind::OpenParen => "'('",
+            TokenKind::CloseParen => "')'",
+            TokenKind::OpenBrace => "'{'",
+            TokenKind::CloseBrace => "'}'",
+            TokenKind::Dot => "'.'",
+            TokenKind::Arrow => "'->'",
+            TokenKind::PlusPlus => "'++'",
+            TokenKind::MinusMinus => "'--'",
+            TokenKind::Ampersand => "'&'",
+            TokenKind::Star => "'*'",
+            TokenKind::Plus => "'+'",
+            TokenKind::Minus => "'-'",
+            TokenKind::Tilde => "'~'",
+            TokenKind::Bang => "'!'",
+            TokenKind::Slash => "'/'",
+            TokenKind::Percent => "'%'",
+            TokenKind::ShiftLeft => "'<<'",
+            TokenKind::ShiftRight => "'>>'",
+            TokenKind::Less => "'<'",
+            TokenKind::Greater => "'>'",
+            TokenKind::LessEqual => "'<='",
+            TokenKind::GreaterEqual => "'>='",
+            TokenKind::EqualEqual => "'=='",
+            TokenKind::NotEqual => "'!='",
+            TokenKind::Caret => "'^'",
+            TokenKind::Pipe => "'|'",
+            TokenKind::AndAnd => "'&&'",
+            TokenKind::OrOr => "'||'",
+            TokenKind::Question => "'?'",
+            TokenKind::Colon => "':'",
+            TokenKind::Semicolon => "';'",
+            TokenKind::Ellipsis => "'...'",
+            TokenKind::Equal => "'='",
+            TokenKind::StarEqual => "'*='",
+            TokenKind::SlashEqual => "'/='",
+            TokenKind::PercentEqual => "'%='",
+            TokenKind::PlusEqual => "'+='",
+            TokenKind::MinusEqual => "'-='",
+            TokenKind::ShiftLeftEqual => "'<<='",
+            TokenKind::ShiftRightEqual => "'>>='",
+            TokenKind::AmpersandEqual => "'&='",
+            TokenKind::CaretEqual => "'^='",
+            TokenKind::PipeEqual => "'|='",
+            TokenKind::Comma => "','",
+            TokenKind::Hash => "'#'",
+            TokenKind::HashHash => "'##'",
+            TokenKind::Whitespace => "whitespace",
+            TokenKind::Comment => "comment",
+            TokenKind::Directive => "preprocessing directive",
+            TokenKind::Invalid => "invalid token",
+        }
+    }
+
+    // Produced only by a lexer with trivia enabled; the parser steps over these.
+    pub fn is_trivia(&self) -> bool {
+        matches!(self, TokenKind::Whitespace | TokenKind::Comment | TokenKind::Directive | TokenKind::Invalid)
     }
 }
 
-pub struct TokenDef {
-    pub kind: TokenKind,
-    pub pattern: Regex
+impl Display for TokenKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.spelling())
+    }
 }
 
-pub fn char_tokens(str: &str) -> Option<TokenKind> {
-    match str.chars().next().unwrap() {
-        '(' => Some(TokenKind::OpenParen),
-        ')' => Some(TokenKind::CloseParen),
-        '{' => Some(TokenKind::OpenBrace),
-        '}' => Some(TokenKind::CloseBrace),
-        ';' => Some(TokenKind::Semicolon),
-        _ => None
+// The C keyword spelled by `text`, if any.
+pub fn keyword(text: &str) -> Option<TokenKind> {
+    match text {
+        "int" => Some(TokenKind::IntKeyword),
+        "void" => Some(TokenKind::VoidKeyword),
+        "return" => Some(TokenKind::ReturnKeyword),
+        _ => None,
     }
 }
 
-pub struct TokenDefResults<'a> {
-    pub def: &'a TokenDef,
-    pub matcher: Match<'a>
+// The longest punctuator at the start of `bytes` and its length. Digraphs give the same
+// kind as the punctuator they stand for.
+pub fn punctuator(bytes: &[u8]) -> Option<(TokenKind, usize)> {
+    let at = |index: usize| bytes.get(index).copied().unwrap_or(0);
+
+    Some(match (at(0), at(1), at(2)) {
+        (b'%', b':', b'%') if at(3) == b':' => (TokenKind::HashHash, 4),
+        (b'.', b'.', b'.') => (TokenKind::Ellipsis, 3),
+        (b'<', b'<', b'=') => (TokenKind::ShiftLeftEqual, 3),
+        (b'>', b'>', b'=') => (TokenKind::ShiftRightEqual, 3),
+        (b'-', b'>', _) => (TokenKind::Arrow, 2),
+        (b'+', b'+', _) => (TokenKind::PlusPlus, 2),
+        (b'-', b'-', _) => (TokenKind::MinusMinus, 2),
+        (b'<', b'<', _) => (TokenKind::ShiftLeft, 2),
+        (b'>', b'>', _) => (TokenKind::ShiftRight, 2),
+        (b'<', b'=', _) => (TokenKind::LessEqual, 2),
+        (b'>', b'=', _) => (TokenKind::GreaterEqual, 2),
+        (b'=', b'=', _) => (TokenKind::EqualEqual, 2),
+        (b'!', b'=', _) => (TokenKind::NotEqual, 2),
+        (b'&', b'&', _) => (TokenKind::AndAnd, 2),
+        (b'|', b'|', _) => (TokenKind::OrOr, 2),
+        (b'*', b'=', _) => (TokenKind::StarEqual, 2),
+        (b'/', b'=', _) => (TokenKind::SlashEqual, 2),
+        (b'%', b'=', _) => (TokenKind::PercentEqual, 2),
+        (b'+', b'=', _) => (TokenKind::PlusEqual, 2),
+        (b'-', b'=', _) => (TokenKind::MinusEqual, 2),
+        (b'&', b'=', _) => (TokenKind::AmpersandEqual, 2),
+        (b'^', b'=', _) => (TokenKind::CaretEqual, 2),
+        (b'|', b'=', _) => (TokenKind::PipeEqual, 2),
+        (b'#', b'#', _) => (TokenKind::HashHash, 2),
+        (b'<', b':', _) => (TokenKind::OpenBracket, 2),
+        (b':', b'>', _) => (TokenKind::CloseBracket, 2),
+        (b'<', b'%', _) => (TokenKind::OpenBrace, 2),
+        (b'%', b'>', _) => (TokenKind::CloseBrace, 2),
+        (b'%', b':', _) => (TokenKind::Hash, 2),
+        (b'[', _, _) => (TokenKind::OpenBracket, 1),
+        (b']', _, _) => (TokenKind::CloseBracket, 1),
+        (b'(', _, _) => (TokenKind::OpenParen, 1),
+        (b')', _, _) => (TokenKind::CloseParen, 1),
+        (b'{', _, _) => (TokenKind::OpenBrace, 1),
+        (b'}', _, _) => (TokenKind::CloseBrace, 1),
+        (b'.', _, _) => (TokenKind::Dot, 1),
+        (b'&', _, _) => (TokenKind::Ampersand, 1),
+        (b'*', _, _) => (TokenKind::Star, 1),
+        (b'+', _, _) => (TokenKind::Plus, 1),
+        (b'-', _, _) => (TokenKind::Minus, 1),
+        (b'~', _, _) => (TokenKind::Tilde, 1),
+        (b'!', _, _) => (TokenKind::Bang, 1),
+        (b'/', _, _) => (TokenKind::Slash, 1),
+        (b'%', _, _) => (TokenKind::Percent, 1),
+        (b'<', _, _) => (TokenKind::Less, 1),
+        (b'>', _, _) => (TokenKind::Greater, 1),
+        (b'^', _, _) => (TokenKind::Caret, 1),
+        (b'|', _, _) => (TokenKind::Pipe, 1),
+        (b'?', _, _) => (TokenKind::Question, 1),
+        (b':', _, _) => (TokenKind::Colon, 1),
+        (b';', _, _) => (TokenKind::Semicolon, 1),
+        (b'=', _, _) => (TokenKind::Equal, 1),
+        (b',', _, _) => (TokenKind::Comma, 1),
+        (b'#', _, _) => (TokenKind::Hash, 1),
+        _ => return None,
+    })
 }
 
 #[derive(Clone, Eq, PartialEq, Debug)]
@@ -57,42 +229,6 @@ impl Token {
         Token { kind, span }
     }
     pub fn explain(&self, source: &str) -> String {
-        format!("[{}]:{} text: \"{}\"", self.kind, self.span, &source[self.span.range()])
+        format!("[{:?}]:{} text: \"{}\"", self.kind, self.span, &source[self.span.range()])
     }
 }
-
-pub fn whitespace() -> Regex {
-    Regex::new(r"^\s+").unwrap()
-}
-
-pub fn keywords() -> HashMap<&'static str, TokenKind> {
-    let mut map = HashMap::new();
-    map.insert("int", TokenKind::IntKeyword);
-    map.insert("void", TokenKind::VoidKeyword);
-    map.insert("return", TokenKind::ReturnKeyword);
-    map
-}
-
-pub fn identifiers_or_constant() -> Regex {
-    Regex::new(r"(^[a-zA-Z_][0-9A-Za-z_]*\b)|(^[0-9]+\b)").unwrap()
-}
-
-pub fn multiline_comment_start() -> Regex {
-    Regex::new(r"^/\*").unwrap()
-}
-
-pub fn multiline_comment_end() -> Regex {
-    Regex::new(r"\*/").unwrap()
-}
-
-pub fn multiline_comment_start_or_end() -> Regex {
-    Regex::new(r"/\*|\*/").unwrap()
-}
-
-pub fn single_line_comment_start() -> Regex {
-    Regex::new(r"^//").unwrap()
-}
-
-pub fn newline() -> Regex {
-    Regex::new(r"\n|\r\n").unwrap()
-}
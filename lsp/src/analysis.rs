@@ -0,0 +1,312 @@
+use std::ops::Range;
+use ariadne::Span as ASpan;
+use common::diagnostics::{Diagnostic, Label, Severity};
+use common::source_file::{SourceFile, TextEdit};
+use common::span::Span;
+use common::warnings::WarningOptions;
+use lexer::lexer::Lexer;
+use lexer::tokens::{Token, TokenKind};
+use parser::parser::Parser;
+use parser::syntax::{NodeKind, SyntaxNode, SyntaxToken};
+use parser::token_collection::TokenCollection;
+use parser::utilities::parse_integer;
+use preprocessor::preprocessor::{preprocess, Preprocessed, PreprocessorOptions};
+
+// What the server knows about an open document.
+pub struct Analysis {
+    // with trivia, for relexing after an edit
+    tokens: Vec<Token>,
+    // the document as written, before preprocessing
+    pub tree: SyntaxNode,
+    // diagnostics pointing into the document, as `niamc` would report them
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+// A function defined in the document.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    // the whole definition
+    pub range: Range<usize>,
+    // the name in its declarator
+    pub selection: Range<usize>,
+}
+
+pub fn analyze(file: &SourceFile, options: &PreprocessorOptions, warnings: &WarningOptions) -> Analysis {
+    let (tokens, _) = lexer(file, options).run();
+    let tree = parse(file, &tokens);
+
+    Analysis { tokens, tree, diagnostics: diagnose(file, options, warnings) }
+}
+
+fn lexer<'a>(file: &'a SourceFile, options: &PreprocessorOptions) -> Lexer<'a> {
+    Lexer::new_with_unit(file.text(), file.id.clone()).with_nested_comments(options.nested_comments).with_trivia(true)
+}
+
+fn parse(file: &SourceFile, tokens: &[Token]) -> SyntaxNode {
+    let mut parser = Parser::new(TokenCollection::new(tokens.to_vec()), file.text());
+    parser.run();
+    parser.syntax_tree()
+}
+
+// The compiler's diagnostics for the file. They take preprocessing it, so unlike the syntax
+// tree they are always worked out from the whole text. Those in built-in definitions can't be
+// shown against it.
+pub fn diagnose(file: &SourceFile, options: &PreprocessorOptions, warnings: &WarningOptions) -> Vec<Diagnostic> {
+    compile(file, options).into_iter()
+        .filter(|diagnostic| diagnostic.span.source() == &file.id)
+        .filter_map(|diagnostic| warnings.apply(diagnostic))
+        .collect()
+}
+
+// The diagnostics of preprocessing, lexing and parsing the file, with those in headers moved
+// onto the `#include`s that brought them in.
+fn compile(file: &SourceFile, options: &PreprocessorOptions) -> Vec<Diagnostic> {
+    let preprocessed = preprocess(file, options);
+    let diagnostics = phases(&preprocessed, options);
+
+    diagnostics.into_iter().map(|diagnostic| at_include(diagnostic, file, &preprocessed)).collect()
+}
+
+// Stops after the first phase that fails, like the compiler does.
+fn phases(preprocessed: &Preprocessed, options: &PreprocessorOptions) -> Vec<Diagnostic> {
+    let failed = |diagnostics: &[Diagnostic]| diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error);
+    let mut diagnostics: Vec<Diagnostic> = preprocessed.errors.iter().map(|error| error.to_diagnostic()).collect();
+
+    if failed(&diagnostics) {
+        return diagnostics;
+    }
+
+    let (tokens, errors) = Lexer::new_with_unit(&preprocessed.text, preprocessed.map.output.clone())
+        .with_nested_comments(options.nested_comments)
+        .run();
+    diagnostics.extend(errors.iter().map(|error| preprocessed.map.map_diagnostic(error.to_diagnostic())));
+
+    if failed(&diagnostics) {
+        return diagnostics;
+    }
+
+    let (_, errors) = Parser::new(TokenCollection::new(tokens), &preprocessed.text).run();
+    let eof = Span::new_with_unit(preprocessed.text.len(), 0, preprocessed.map.output.clone());
+    diagnostics.extend(errors.iter().map(|error| preprocessed.map.map_diagnostic(error.to_diagnostic(eof.clone()))));
+
+    diagnostics
+}
+
+// A diagnostic in a header moved onto the `#include` in `file` the header came in through,
+// with notes on where it really is.
+fn at_include(diagnostic: Diagnostic, file: &SourceFile, preprocessed: &Preprocessed) -> Diagnostic {
+    let mut site = diagnostic.span.clone();
+    let mut notes = Vec::new();
+
+    while site.source() != &file.id {
+        let Some(include) = preprocessed.included_at.get(site.source()) else {
+            return diagnostic;
+        };
+
+        if let Some(header) = preprocessed.included.iter().find(|header| &header.id == site.source()) {
+            let (line, column) = header.line_pos_or_end(site.start());
+            let how = if notes.is_empty() { "in" } else { "included from" };
+            notes.push(format!("{} {}:{}:{}", how, header.filename, line, column));
+        }
+
+        site = include.clone();
+    }
+
+    if notes.is_empty() {
+        return diagnostic;
+    }
+
+    notes.extend(diagnostic.notes.iter().cloned());
+
+    let label = Label { span: site.clone(), message: "in the header included here".to_string() };
+
+    Diagnostic { span: site, labels: vec![label], notes, fixes: Vec::new(), ..diagnostic }
+}
+
+impl Analysis {
+    // Catches the tokens and tree up with `edit`, which `file` already has applied, relexing
+    // and reparsing only around it where that gives the same result.
+    pub fn edit(&mut self, file: &SourceFile, edit: &TextEdit, options: &PreprocessorOptions) {
+        let relexed = lexer(file, options).relex(&mut self.tokens, edit);
+
+        self.tree = Parser::reparse(&self.tree, &self.tokens, relexed, edit, file.text())
+            .unwrap_or_else(|| parse(file, &self.tokens));
+    }
+
+    // The identifier under the cursor; a cursor right after one counts as well.
+    fn identifier_at(&self, offset: usize) -> Option<SyntaxToken> {
+        let is_identifier = |token: &SyntaxToken| token.kind() == TokenKind::Identifier;
+
+        self.tree.token_at(offset).filter(is_identifier)
+            .or_else(|| self.tree.token_at(offset.checked_sub(1)?).filter(is_identifier))
+    }
+
+    // Every identifier in the document spelled `name`.
+    fn occurrences(&self, name: &str) -> Vec<SyntaxToken> {
+        self.tree.tokens().into_iter()
+            .filter(|token| token.kind() == TokenKind::Identifier && token.text() == name)
+            .collect()
+    }
+
+    // Where the identifier under the cursor is declared.
+    pub fn definition(&self, offset: usize) -> Option<Range<usize>> {
+        let identifier = self.identifier_at(offset)?;
+
+        self.occurrences(identifier.text()).into_iter()
+            .find(|token| token.parent().kind() == NodeKind::Declarator)
+            .map(|token| token.range())
+    }
+
+    pub fn references(&self, offset: usize, include_declaration: bool) -> Vec<Range<usize>> {
+        let Some(identifier) = self.identifier_at(offset) else {
+            return Vec::new();
+        };
+
+        self.occurrences(identifier.text()).into_iter()
+            .filter(|token| include_declaration || token.parent().kind() != NodeKind::Declarator)
+            .map(|token| token.range())
+            .collect()
+    }
+
+    // Markdown describing what is under the cursor, and the range it describes: the type of an
+    // integer constant, or the declaration of an identifier.
+    pub fn hover(&self, offset: usize) -> Option<(String, Range<usize>)> {
+        if let Some(token) = self.tree.token_at(offset) && token.kind() == TokenKind::Constant {
+            let index = self.tokens.partition_point(|lexed| lexed.span.range().start < token.range().start);
+            let constant = parse_integer(token.text(), &self.tokens.get(index)?.span).ok()?;
+
+            return Some((format!("`{}`: `{}` = {}", token.text(), constant.ty, constant.value), token.range()));
+        }
+
+        let identifier = self.identifier_at(offset)?;
+        let declaration = self.occurrences(identifier.text()).into_iter()
+            .find(|token| token.parent().kind() == NodeKind::Declarator)?;
+
+        Some((format!("```c\n{}\n```", signature(declaration.parent())), identifier.range()))
+    }
+
+    pub fn symbols(&self) -> Vec<Symbol> {
+        self.tree.descendants().into_iter()
+            .filter(|node| node.kind() == NodeKind::FunctionDefinition)
+            .filter_map(|function| {
+                let declarator = function.children().find(|node| node.kind() == NodeKind::Declarator)?;
+                let name = declarator.tokens().into_iter().find(|token| token.kind() == TokenKind::Identifier)?;
+
+                Some(Symbol { name: name.text().to_string(), range: function.range(), selection: name.range() })
+            })
+            .collect()
+    }
+}
+
+// A declarator on one line with single spaces, whatever its layout in the source.
+fn signature(declarator: &SyntaxNode) -> String {
+    let mut signature = String::new();
+    let mut previous = None;
+
+    for token in declarator.tokens().into_iter().filter(|token| !token.kind().is_trivia()) {
+        let tight = matches!((previous, token.kind()),
+            (None | Some(TokenKind::OpenParen), _) | (_, TokenKind::OpenParen | TokenKind::CloseParen | TokenKind::Comma));
+
+        if !tight {
+            signature.push(' ');
+        }
+
+        signature.push_str(token.text());
+        previous = Some(token.kind());
+    }
+
+    signature
+}
+
+#[cfg(test)]
+mod tests {
+    use common::diagnostics::{apply_fixes, FixIt};
+    use common::source_file::Id;
+    use super::*;
+
+    const TEXT: &str = "/* entry */\nint main(\n    void\n) {\n    return 0x1Fu;\n}\n";
+
+    fn analysis(text: &str) -> Analysis {
+        let file = SourceFile::from_string(Id::Main, text);
+        analyze(&file, &PreprocessorOptions::default(), &WarningOptions::default())
+    }
+
+    #[test]
+    fn navigation() {
+        let analysis = analysis(TEXT);
+        let main = TEXT.find("main").unwrap();
+
+        assert_eq!(analysis.definition(main + 2), Some(main..main + 4));
+        assert_eq!(analysis.definition(main + 4), Some(main..main + 4));
+        assert_eq!(analysis.definition(0), None);
+        assert_eq!(analysis.references(main, true), vec![main..main + 4]);
+        assert!(analysis.references(main, false).is_empty());
+    }
+
+    #[test]
+    fn hover() {
+        let analysis = analysis(TEXT);
+        let constant = TEXT.find("0x1Fu").unwrap();
+
+        assert_eq!(analysis.hover(constant + 1), Some(("`0x1Fu`: `unsigned int` = 31".to_string(), constant..constant + 5)));
+        assert_eq!(analysis.hover(TEXT.find("main").unwrap()).unwrap().0, "```c\nint main(void)\n```");
+        assert_eq!(analysis.hover(0), None);
+    }
+
+    #[test]
+    fn symbols() {
+        let main = TEXT.find("main").unwrap();
+
+        assert_eq!(analysis(TEXT).symbols(), vec![Symbol { name: "main".to_string(), range: 12..TEXT.len() - 1, selection: main..main + 4 }]);
+    }
+
+    #[test]
+    fn edits() {
+        let mut file = SourceFile::from_string(Id::Main, TEXT);
+        let mut analysis = analyze(&file, &PreprocessorOptions::default(), &WarningOptions::default());
+        let constant = TEXT.find("0x1Fu").unwrap();
+        let main = TEXT.find("main").unwrap();
+
+        for edit in [TextEdit::new(constant..constant + 5, "42"), TextEdit::new(main..main + 4, "start")] {
+            file.apply(&edit);
+            analysis.edit(&file, &edit, &PreprocessorOptions::default());
+
+            assert_eq!(analysis.tree.to_string(), file.text());
+        }
+
+        // the constant moved along with the longer name in front of it
+        assert_eq!(analysis.hover(constant + 1).unwrap().0, "`42`: `int` = 42");
+        assert_eq!(analysis.symbols()[0].name, "start");
+    }
+
+    #[test]
+    fn diagnostics_stop_at_the_first_failing_phase() {
+        assert!(analysis(TEXT).diagnostics.is_empty());
+
+        let lexing = analysis("int main(void) { return @; }");
+        assert_eq!(lexing.diagnostics.len(), 1);
+        assert_eq!(lexing.diagnostics[0].span.range(), 24..25);
+
+        let parsing = analysis("int main(void) { return 0 }");
+        assert!(!parsing.diagnostics.is_empty());
+        assert!(parsing.diagnostics.iter().all(|diagnostic| diagnostic.severity == Severity::Error));
+    }
+
+    #[test]
+    fn fixes_stay_out_of_macro_expansions() {
+        // the ';' belongs inside `BAD`, which `--fix` can't edit from here
+        let text = "#define BAD return 1 1\n#define W BAD\nint main(void) { W; }\n";
+        let diagnostics = analysis(text).diagnostics;
+        let fixes: Vec<FixIt> = diagnostics.iter().flat_map(|diagnostic| diagnostic.fixes.clone()).collect();
+
+        assert!(!diagnostics.is_empty());
+        assert_eq!(apply_fixes(text, &fixes), text);
+
+        // right after a whole expansion is still fine
+        let text = "#define ZERO 0\nint main(void) { return ZERO }\n";
+        let fixes: Vec<FixIt> = analysis(text).diagnostics.iter().flat_map(|diagnostic| diagnostic.fixes.clone()).collect();
+
+        assert_eq!(apply_fixes(text, &fixes), "#define ZERO 0\nint main(void) { return ZERO; }\n");
+    }
+}
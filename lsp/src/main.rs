@@ -0,0 +1,29 @@
+// niamc-lsp: the compiler's diagnostics and navigation for editors, over stdio
+
+use std::error::Error;
+use lsp_server::Connection;
+use lsp_types::InitializeParams;
+use crate::server::{capabilities, Encoding, Server};
+
+mod analysis;
+mod server;
+
+fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (connection, threads) = Connection::stdio();
+
+    let (id, params) = connection.initialize_start()?;
+    let params: InitializeParams = serde_json::from_value(params)?;
+    let encoding = Encoding::negotiate(&params);
+    let result = serde_json::json!({
+        "capabilities": capabilities(encoding),
+        "serverInfo": { "name": "niamc-lsp", "version": env!("CARGO_PKG_VERSION") },
+    });
+    connection.initialize_finish(id, result)?;
+
+    Server::new(encoding).run(&connection)?;
+
+    drop(connection);
+    threads.join()?;
+
+    Ok(())
+}
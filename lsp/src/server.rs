@@ -0,0 +1,635 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::time::{Duration, Instant};
+use ariadne::Span as ASpan;
+use common::diagnostics::{Diagnostic, Severity};
+use common::source_file::{Id, SourceFile, TextEdit};
+use common::span::Span;
+use common::warnings::WarningOptions;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, LogMessage, Notification as _, PublishDiagnostics};
+use lsp_types::request::{DocumentSymbolRequest, GotoDefinition, HoverRequest, References, Request as _};
+use lsp_types::{
+    DiagnosticRelatedInformation, DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse, GotoDefinitionParams,
+    GotoDefinitionResponse, Hover, HoverContents, HoverParams, HoverProviderCapability, InitializeParams, Location,
+    LogMessageParams, MarkupContent, MarkupKind, MessageType, NumberOrString, OneOf, Position, PositionEncodingKind, PublishDiagnosticsParams, Range,
+    ReferenceParams, ServerCapabilities, SymbolKind, TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+use preprocessor::preprocessor::PreprocessorOptions;
+use crate::analysis::{analyze, diagnose, Analysis};
+
+// How the client counts the characters of a line in a `Position`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Encoding {
+    // the protocol's default
+    Utf16,
+    Utf32,
+}
+
+impl Encoding {
+    // Code points match `SourceFile` columns, so they are taken whenever the client offers them.
+    pub fn negotiate(params: &InitializeParams) -> Encoding {
+        let offered = params.capabilities.general.as_ref().and_then(|general| general.position_encodings.as_ref());
+
+        match offered {
+            Some(encodings) if encodings.contains(&PositionEncodingKind::UTF32) => Encoding::Utf32,
+            _ => Encoding::Utf16,
+        }
+    }
+
+    fn kind(&self) -> PositionEncodingKind {
+        match self {
+            Encoding::Utf16 => PositionEncodingKind::UTF16,
+            Encoding::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+
+    fn width(&self, c: char) -> u32 {
+        match self {
+            Encoding::Utf16 => c.len_utf16() as u32,
+            Encoding::Utf32 => 1,
+        }
+    }
+}
+
+pub fn capabilities(encoding: Encoding) -> ServerCapabilities {
+    ServerCapabilities {
+        position_encoding: Some(encoding.kind()),
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::INCREMENTAL)),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    }
+}
+
+struct Document {
+    file: SourceFile,
+    analysis: Analysis,
+}
+
+// How long typing has to pause before the diagnostics of the edited documents are worked out
+// again, which takes preprocessing and compiling the whole text.
+const DIAGNOSTICS_DELAY: Duration = Duration::from_millis(150);
+
+pub struct Server {
+    encoding: Encoding,
+    options: PreprocessorOptions,
+    warnings: WarningOptions,
+    documents: HashMap<Url, Document>,
+    // documents edited since their diagnostics were published, and when to publish them
+    stale: HashSet<Url>,
+    deadline: Option<Instant>,
+}
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+impl Server {
+    pub fn new(encoding: Encoding) -> Server {
+        Server {
+            encoding,
+            options: PreprocessorOptions::default(),
+            warnings: WarningOptions::default(),
+            documents: HashMap::new(),
+            stale: HashSet::new(),
+            deadline: None,
+        }
+    }
+
+    // Serves requests until the client asks to shut down.
+    pub fn run(&mut self, connection: &Connection) -> Result<()> {
+        loop {
+            let received = match self.deadline {
+                Some(deadline) => connection.receiver.recv_deadline(deadline),
+                None => connection.receiver.recv().map_err(Into::into),
+            };
+
+            let message = match received {
+                Ok(message) => message,
+                Err(err) if err.is_timeout() => {
+                    for published in self.diagnose_stale() {
+                        connection.sender.send(Message::Notification(published))?;
+                    }
+
+                    continue;
+                }
+                Err(_) => break,
+            };
+
+            match message {
+                Message::Request(request) => {
+                    if connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+
+                    connection.sender.send(Message::Response(self.request(request)))?;
+                }
+                Message::Notification(notification) => {
+                    let method = notification.method.clone();
+                    // a notification that can't be handled is only reported, the session goes on
+                    let replies = self.notification(notification)
+                        .unwrap_or_else(|err| vec![log(MessageType::ERROR, format!("couldn't handle '{}': {}", method, err))]);
+
+                    for reply in replies {
+                        connection.sender.send(Message::Notification(reply))?;
+                    }
+                }
+                Message::Response(_) => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn request(&self, request: Request) -> Response {
+        let id = request.id.clone();
+
+        let result = match request.method.as_str() {
+            GotoDefinition::METHOD => self.definition(request),
+            References::METHOD => self.references(request),
+            HoverRequest::METHOD => self.hover(request),
+            DocumentSymbolRequest::METHOD => self.symbols(request),
+            method => return Response::new_err(id, ErrorCode::MethodNotFound as i32, format!("unsupported request '{}'", method)),
+        };
+
+        match result {
+            Ok(result) => Response::new_ok(id, result),
+            Err(err) => Response::new_err(id, ErrorCode::InvalidParams as i32, err.to_string()),
+        }
+    }
+
+    // Keeps the documents in sync, answering with the diagnostics of whatever changed. Those of
+    // an edited document wait until the edits pause.
+    fn notification(&mut self, notification: Notification) -> Result<Vec<Notification>> {
+        let url = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams = serde_json::from_value(notification.params)?;
+                self.update(params.text_document.uri.clone(), &params.text_document.text);
+                params.text_document.uri
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: DidChangeTextDocumentParams = serde_json::from_value(notification.params)?;
+                self.change(&params)?;
+                self.stale.insert(params.text_document.uri);
+                self.deadline = Some(Instant::now() + DIAGNOSTICS_DELAY);
+                return Ok(Vec::new());
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams = serde_json::from_value(notification.params)?;
+                self.documents.remove(&params.text_document.uri);
+                self.stale.remove(&params.text_document.uri);
+                // clear what was shown for the document
+                return Ok(vec![publish(params.text_document.uri, Vec::new())]);
+            }
+            _ => return Ok(Vec::new()),
+        };
+
+        self.stale.remove(&url);
+        Ok(vec![self.published(url)])
+    }
+
+    // Checks the documents edited since their diagnostics were last published.
+    fn diagnose_stale(&mut self) -> Vec<Notification> {
+        self.deadline = None;
+        let stale: Vec<Url> = self.stale.drain().collect();
+
+        stale.into_iter().map(|url| {
+            if let Some(document) = self.documents.get_mut(&url) {
+                document.analysis.diagnostics = diagnose(&document.file, &self.options, &self.warnings);
+            }
+
+            self.published(url)
+        }).collect()
+    }
+
+    fn published(&self, url: Url) -> Notification {
+        let diagnostics = match self.documents.get(&url) {
+            Some(document) => document.analysis.diagnostics.iter().map(|diagnostic| self.diagnostic(&url, &document.file, diagnostic)).collect(),
+            None => Vec::new(),
+        };
+
+        publish(url, diagnostics)
+    }
+
+    fn update(&mut self, url: Url, text: &str) {
+        let mut file = SourceFile::from_string(Id::Unit(url.to_string()), text);
+
+        // includes are looked up next to the file, so it needs its real path
+        if let Ok(path) = url.to_file_path() {
+            file.filename = path.to_string_lossy().into_owned();
+        }
+
+        let analysis = analyze(&file, &self.options, &self.warnings);
+        self.documents.insert(url, Document { file, analysis });
+    }
+
+    // Applies the changes in order, each to the text the one before left.
+    fn change(&mut self, params: &DidChangeTextDocumentParams) -> Result<()> {
+        let url = &params.text_document.uri;
+        let mut document = self.documents.remove(url).ok_or_else(|| format!("'{}' is not open", url))?;
+
+        for change in &params.content_changes {
+            let Some(range) = change.range else {
+                // the whole text
+                self.update(url.clone(), &change.text);
+                document = self.documents.remove(url).expect("the document was just analyzed");
+                continue;
+            };
+
+            let start = self.offset(&document.file, range.start);
+            let edit = TextEdit::new(start..self.offset(&document.file, range.end).max(start), change.text.as_str());
+
+            document.file.apply(&edit);
+            document.analysis.edit(&document.file, &edit, &self.options);
+        }
+
+        self.documents.insert(url.clone(), document);
+        Ok(())
+    }
+
+    fn document(&self, url: &Url) -> Result<&Document> {
+        self.documents.get(url).ok_or_else(|| format!("'{}' is not open", url).into())
+    }
+
+    // The document and byte offset a request points at.
+    fn locate(&self, url: &Url, position: Position) -> Result<(&Document, usize)> {
+        let document = self.document(url)?;
+        Ok((document, self.offset(&document.file, position)))
+    }
+
+    fn definition(&self, request: Request) -> Result<serde_json::Value> {
+        let params: GotoDefinitionParams = serde_json::from_value(request.params)?;
+        let url = params.text_document_position_params.text_document.uri;
+        let (document, offset) = self.locate(&url, params.text_document_position_params.position)?;
+
+        let response = document.analysis.definition(offset).map(|range| {
+            GotoDefinitionResponse::Scalar(Location { uri: url.clone(), range: self.range(&document.file, range) })
+        });
+
+        Ok(serde_json::to_value(response)?)
+    }
+
+    fn references(&self, request: Request) -> Result<serde_json::Value> {
+        let params: ReferenceParams = serde_json::from_value(request.params)?;
+        let url = params.text_document_position.text_document.uri;
+        let (document, offset) = self.locate(&url, params.text_document_position.position)?;
+
+        let locations: Vec<Location> = document.analysis.references(offset, params.context.include_declaration).into_iter()
+            .map(|range| Location { uri: url.clone(), range: self.range(&document.file, range) })
+            .collect();
+
+        Ok(serde_json::to_value(locations)?)
+    }
+
+    fn hover(&self, request: Request) -> Result<serde_json::Value> {
+        let params: HoverParams = serde_json::from_value(request.params)?;
+        let url = params.text_document_position_params.text_document.uri;
+        let (document, offset) = self.locate(&url, params.text_document_position_params.position)?;
+
+        let hover = document.analysis.hover(offset).map(|(value, range)| Hover {
+            contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value }),
+            range: Some(self.range(&document.file, range)),
+        });
+
+        Ok(serde_json::to_value(hover)?)
+    }
+
+    fn symbols(&self, request: Request) -> Result<serde_json::Value> {
+        let params: DocumentSymbolParams = serde_json::from_value(request.params)?;
+        let document = self.document(&params.text_document.uri)?;
+
+        #[allow(deprecated)]
+        let symbols: Vec<DocumentSymbol> = document.analysis.symbols().into_iter()
+            .map(|symbol| DocumentSymbol {
+                name: symbol.name,
+                detail: None,
+                kind: SymbolKind::FUNCTION,
+                tags: None,
+                deprecated: None,
+                range: self.range(&document.file, symbol.range),
+                selection_range: self.range(&document.file, symbol.selection),
+                children: None,
+            })
+            .collect();
+
+        Ok(serde_json::to_value(DocumentSymbolResponse::Nested(symbols))?)
+    }
+
+    fn diagnostic(&self, url: &Url, file: &SourceFile, diagnostic: &Diagnostic) -> lsp_types::Diagnostic {
+        let mut message = diagnostic.message.clone();
+
+        for note in &diagnostic.notes {
+            message.push_str(&format!("\nnote: {}", note));
+        }
+
+        for help in &diagnostic.help {
+            message.push_str(&format!("\nhelp: {}", help));
+        }
+
+        // labels in other files, such as headers, can't be shown against this document
+        let related: Vec<DiagnosticRelatedInformation> = diagnostic.labels.iter()
+            .filter(|label| label.span.source() == &file.id)
+            .map(|label| DiagnosticRelatedInformation {
+                location: Location { uri: url.clone(), range: self.span(file, &label.span) },
+                message: label.message.clone(),
+            })
+            .collect();
+
+        lsp_types::Diagnostic {
+            range: self.span(file, &diagnostic.span),
+            severity: Some(match diagnostic.severity {
+                Severity::Error => DiagnosticSeverity::ERROR,
+                Severity::Warning => DiagnosticSeverity::WARNING,
+                Severity::Note => DiagnosticSeverity::INFORMATION,
+            }),
+            code: diagnostic.code.map(|code| NumberOrString::String(code.code().to_string()))
+                .or_else(|| diagnostic.warning.map(|warning| NumberOrString::String(format!("-W{}", warning.name())))),
+            source: Some("niamc".to_string()),
+            message,
+            related_information: (!related.is_empty()).then_some(related),
+            ..lsp_types::Diagnostic::default()
+        }
+    }
+
+    fn span(&self, file: &SourceFile, span: &Span) -> Range {
+        self.range(file, span.range())
+    }
+
+    fn range(&self, file: &SourceFile, range: std::ops::Range<usize>) -> Range {
+        Range { start: self.position(file, range.start), end: self.position(file, range.end) }
+    }
+
+    fn position(&self, file: &SourceFile, offset: usize) -> Position {
+        let text = file.text();
+        let offset = offset.min(text.len());
+
+        let (line, _) = file.line_pos_or_end(offset);
+        let start = file.offset_from_line_pos(line, 1).unwrap_or(0);
+        let character = text[start..offset].chars().map(|c| self.encoding.width(c)).sum();
+
+        Position { line: line as u32 - 1, character }
+    }
+
+    // A position past the end of its line is the end of the line, one past the last line the
+    // end of the text.
+    fn offset(&self, file: &SourceFile, position: Position) -> usize {
+        let text = file.text();
+        let line = position.line as usize + 1;
+
+        let Some(start) = file.offset_from_line_pos(line, 1) else {
+            return text.len();
+        };
+
+        let end = file.offset_from_line_pos(line + 1, 1).map_or(text.len(), |next| next - 1);
+        let mut character = 0;
+
+        for (index, c) in text[start..end].char_indices() {
+            if character >= position.character {
+                return start + index;
+            }
+
+            character += self.encoding.width(c);
+        }
+
+        end
+    }
+}
+
+fn publish(uri: Url, diagnostics: Vec<lsp_types::Diagnostic>) -> Notification {
+    Notification::new(PublishDiagnostics::METHOD.to_string(), PublishDiagnosticsParams { uri, diagnostics, version: None })
+}
+
+fn log(typ: MessageType, message: String) -> Notification {
+    Notification::new(LogMessage::METHOD.to_string(), LogMessageParams { typ, message })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use lsp_server::RequestId;
+    use serde_json::{json, Value};
+    use super::*;
+
+    fn url() -> Url {
+        Url::parse("file:///tmp/main.c").unwrap()
+    }
+
+    // Starts a server on one end of an in-memory connection and returns the other end.
+    fn start(encoding: Encoding) -> (Connection, thread::JoinHandle<()>) {
+        let (server, client) = Connection::memory();
+        let handle = thread::spawn(move || Server::new(encoding).run(&server).unwrap());
+
+        (client, handle)
+    }
+
+    fn notify(client: &Connection, method: &str, params: Value) {
+        client.sender.send(Message::Notification(Notification::new(method.to_string(), params))).unwrap();
+    }
+
+    fn request(client: &Connection, id: i32, method: &str, params: Value) -> Value {
+        client.sender.send(Message::Request(Request::new(RequestId::from(id), method.to_string(), params))).unwrap();
+
+        match client.receiver.recv().unwrap() {
+            Message::Response(response) => response.result.unwrap_or_else(|| panic!("{:?}", response.error)),
+            message => panic!("expected a response, got {:?}", message),
+        }
+    }
+
+    fn published(client: &Connection) -> PublishDiagnosticsParams {
+        match client.receiver.recv().unwrap() {
+            Message::Notification(notification) if notification.method == PublishDiagnostics::METHOD =>
+                serde_json::from_value(notification.params).unwrap(),
+            message => panic!("expected diagnostics, got {:?}", message),
+        }
+    }
+
+    fn shut_down(client: Connection, handle: thread::JoinHandle<()>) {
+        request(&client, 99, "shutdown", Value::Null);
+        notify(&client, "exit", Value::Null);
+        handle.join().unwrap();
+    }
+
+    fn at(line: u32, character: u32) -> Value {
+        json!({ "textDocument": { "uri": url() }, "position": { "line": line, "character": character } })
+    }
+
+    #[test]
+    fn publishes_diagnostics_on_change() {
+        let (client, handle) = start(Encoding::Utf16);
+
+        notify(&client, DidOpenTextDocument::METHOD, json!({
+            "textDocument": { "uri": url(), "languageId": "c", "version": 1, "text": "int main(void) {\n    return @;\n}\n" }
+        }));
+
+        let diagnostics = published(&client).diagnostics;
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range, Range { start: Position::new(1, 11), end: Position::new(1, 12) });
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(diagnostics[0].source.as_deref(), Some("niamc"));
+
+        notify(&client, DidChangeTextDocument::METHOD, json!({
+            "textDocument": { "uri": url(), "version": 2 },
+            "contentChanges": [{ "text": "int main(void) {\n    return 0;\n}\n" }]
+        }));
+
+        assert!(published(&client).diagnostics.is_empty());
+
+        // edits apply one after the other, in the encoding's units
+        notify(&client, DidChangeTextDocument::METHOD, json!({
+            "textDocument": { "uri": url(), "version": 3 },
+            "contentChanges": [
+                { "range": { "start": { "line": 1, "character": 11 }, "end": { "line": 1, "character": 12 } }, "text": "/* 😀 */ 1" },
+                { "range": { "start": { "line": 1, "character": 21 }, "end": { "line": 1, "character": 21 } }, "text": "u" },
+            ]
+        }));
+
+        assert!(published(&client).diagnostics.is_empty());
+
+        let hover = request(&client, 1, HoverRequest::METHOD, at(1, 20));
+        assert_eq!(hover["contents"]["value"], "`1u`: `unsigned int` = 1");
+
+        shut_down(client, handle);
+    }
+
+    #[test]
+    fn header_errors_show_on_the_include() {
+        let directory = std::env::temp_dir().join(format!("niamc-lsp-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("stop.h"), "int x;\n#error broken\n").unwrap();
+        std::fs::write(directory.join("syntax.h"), "int f(void) { return }\n").unwrap();
+
+        let (client, handle) = start(Encoding::Utf16);
+        let published_for = |header: &str| {
+            let uri = Url::from_file_path(directory.join("main.c")).unwrap();
+            let text = format!("// entry\n#include \"{}\"\nint main(void) {{ return 0; }}\n", header);
+
+            notify(&client, DidOpenTextDocument::METHOD, json!({
+                "textDocument": { "uri": uri, "languageId": "c", "version": 1, "text": text }
+            }));
+
+            published(&client).diagnostics
+        };
+
+        let stop = published_for("stop.h");
+        let syntax = published_for("syntax.h");
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(stop.len(), 1);
+        assert_eq!(stop[0].range, Range { start: Position::new(1, 0), end: Position::new(1, 17) });
+        assert!(stop[0].message.starts_with("#error broken\nnote: in "));
+        assert!(stop[0].message.contains("stop.h:2:1"));
+
+        assert!(!syntax.is_empty());
+        assert_eq!(syntax[0].range.start, Position::new(1, 0));
+        assert!(syntax[0].message.contains("syntax.h:1:22"));
+
+        shut_down(client, handle);
+    }
+
+    #[test]
+    fn diagnostics_wait_for_edits_to_pause() {
+        let mut server = Server::new(Encoding::Utf16);
+        let open = Notification::new(DidOpenTextDocument::METHOD.to_string(), json!({
+            "textDocument": { "uri": url(), "languageId": "c", "version": 1, "text": "int main(void) { return 0; }" }
+        }));
+
+        assert_eq!(server.notification(open).unwrap().len(), 1);
+
+        for (version, text) in [(2, "@"), (3, "1")] {
+            let change = Notification::new(DidChangeTextDocument::METHOD.to_string(), json!({
+                "textDocument": { "uri": url(), "version": version },
+                "contentChanges": [{ "range": { "start": { "line": 0, "character": 24 }, "end": { "line": 0, "character": 25 } }, "text": text }]
+            }));
+
+            assert!(server.notification(change).unwrap().is_empty());
+        }
+
+        assert!(server.deadline.is_some());
+
+        let published = server.diagnose_stale();
+        let params: PublishDiagnosticsParams = serde_json::from_value(published[0].params.clone()).unwrap();
+
+        assert_eq!(published.len(), 1);
+        assert!(params.diagnostics.is_empty());
+        assert!(server.diagnose_stale().is_empty());
+        assert_eq!(server.deadline, None);
+    }
+
+    #[test]
+    fn bad_notifications_are_logged() {
+        let (client, handle) = start(Encoding::Utf16);
+        let change = json!({
+            "textDocument": { "uri": url(), "version": 2 },
+            "contentChanges": [{ "text": "int main(void) { return 0; }" }]
+        });
+
+        for (method, params) in [(DidChangeTextDocument::METHOD, change), (DidOpenTextDocument::METHOD, json!({ "textDocument": 1 }))] {
+            notify(&client, method, params);
+
+            match client.receiver.recv().unwrap() {
+                Message::Notification(notification) if notification.method == LogMessage::METHOD => {
+                    let params: LogMessageParams = serde_json::from_value(notification.params).unwrap();
+                    assert_eq!(params.typ, MessageType::ERROR);
+                    assert!(params.message.starts_with(&format!("couldn't handle '{}'", method)));
+                }
+                message => panic!("expected a log message, got {:?}", message),
+            }
+        }
+
+        // and the server still serves
+        notify(&client, DidOpenTextDocument::METHOD, json!({
+            "textDocument": { "uri": url(), "languageId": "c", "version": 1, "text": "int main(void) { return 0; }" }
+        }));
+
+        assert!(published(&client).diagnostics.is_empty());
+        shut_down(client, handle);
+    }
+
+    #[test]
+    fn navigation_and_symbols() {
+        let (client, handle) = start(Encoding::Utf16);
+
+        notify(&client, DidOpenTextDocument::METHOD, json!({
+            "textDocument": { "uri": url(), "languageId": "c", "version": 1, "text": "/* é */ int main(void) {\n    return 07L;\n}\n" }
+        }));
+        published(&client);
+
+        let main = json!({ "start": { "line": 0, "character": 12 }, "end": { "line": 0, "character": 16 } });
+        assert_eq!(request(&client, 1, GotoDefinition::METHOD, at(0, 14)), json!({ "uri": url(), "range": main }));
+        assert_eq!(request(&client, 2, GotoDefinition::METHOD, at(1, 0)), Value::Null);
+
+        let mut references = at(0, 13);
+        references["context"] = json!({ "includeDeclaration": true });
+        assert_eq!(request(&client, 3, References::METHOD, references), json!([{ "uri": url(), "range": main }]));
+
+        let hover = request(&client, 4, HoverRequest::METHOD, at(1, 12));
+        assert_eq!(hover["contents"]["value"], "`07L`: `long` = 7");
+
+        let symbols = request(&client, 5, DocumentSymbolRequest::METHOD, json!({ "textDocument": { "uri": url() } }));
+        assert_eq!(symbols[0]["name"], "main");
+        assert_eq!(symbols[0]["selectionRange"], main);
+
+        shut_down(client, handle);
+    }
+
+    #[test]
+    fn positions_in_either_encoding() {
+        let text = "a\u{1F600}b\n\nc";
+        let file = SourceFile::from_string(Id::Main, text);
+        let utf16 = Server::new(Encoding::Utf16);
+        let utf32 = Server::new(Encoding::Utf32);
+        let b = text.find('b').unwrap();
+
+        assert_eq!(utf16.position(&file, b), Position::new(0, 3));
+        assert_eq!(utf32.position(&file, b), Position::new(0, 2));
+        assert_eq!(utf16.offset(&file, Position::new(0, 3)), b);
+        assert_eq!(utf32.offset(&file, Position::new(0, 2)), b);
+        assert_eq!(utf16.offset(&file, Position::new(0, 40)), b + 1);
+        assert_eq!(utf16.position(&file, text.len()), Position::new(2, 1));
+        assert_eq!(utf16.offset(&file, Position::new(1, 0)), b + 2);
+        assert_eq!(utf16.offset(&file, Position::new(7, 0)), text.len());
+    }
+}
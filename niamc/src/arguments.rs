@@ -0,0 +1,95 @@
+use std::str::FromStr;
+use argh::TopLevelCommand;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ErrorFormat {
+    Human,
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(ErrorFormat::Human),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(format!("unknown error format '{}', expected 'human' or 'json'", s)),
+        }
+    }
+}
+
+// Short options written together with their value, as in `-Wall`, `-Iinclude` or `-DX=1`.
+const JOINED_OPTIONS: &[&str] = &["-W", "-I", "-D"];
+
+// argh only accepts `--option value`, so `--option=value` is split before parsing, and
+// `-Wname` becomes `-W name`.
+fn normalize(args: Vec<String>) -> Vec<String> {
+    let mut normalized = Vec::with_capacity(args.len());
+    let mut options_ended = false;
+
+    for arg in args {
+        if arg == "--" {
+            options_ended = true;
+        }
+
+        if options_ended {
+            normalized.push(arg);
+            continue;
+        }
+
+        match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => {
+                normalized.push(name.to_string());
+                normalized.push(value.to_string());
+            }
+            _ => match JOINED_OPTIONS.iter().find(|option| arg.starts_with(*option) && arg.len() > option.len()) {
+                Some(option) => {
+                    normalized.push(option.to_string());
+                    normalized.push(arg[option.len()..].to_string());
+                }
+                None => normalized.push(arg),
+            },
+        }
+    }
+
+    normalized
+}
+
+// Same as `argh::from_env`, with the arguments normalized first.
+pub fn from_env<T: TopLevelCommand>() -> T {
+    parse_env(None)
+}
+
+// Like `from_env` for `niamc <subcommand> ...`, with the subcommand's name taken off.
+pub fn subcommand_from_env<T: TopLevelCommand>(subcommand: &str) -> T {
+    parse_env(Some(subcommand))
+}
+
+fn parse_env<T: TopLevelCommand>(subcommand: Option<&str>) -> T {
+    let strings: Vec<String> = std::env::args().collect();
+    let program = std::path::Path::new(&strings[0])
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(&strings[0])
+        .to_string();
+    let (cmd, skip) = match subcommand {
+        Some(subcommand) => (format!("{} {}", program, subcommand), 2),
+        None => (program, 1),
+    };
+    let rest = normalize(strings[skip..].to_vec());
+    let rest: Vec<&str> = rest.iter().map(|s| s.as_str()).collect();
+
+    T::from_args(&[&cmd], &rest).unwrap_or_else(|early_exit| {
+        std::process::exit(match early_exit.status {
+            Ok(()) => {
+                println!("{}", early_exit.output);
+                0
+            }
+            Err(()) => {
+                eprintln!("{}\nRun {} --help for more information.", early_exit.output, cmd);
+                1
+            }
+        })
+    })
+}
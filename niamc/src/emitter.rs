@@ -0,0 +1,68 @@
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::io;
+use common::diagnostics::{apply_fixes, Diagnostic, FixIt, Severity};
+use ariadne::Span as ASpan;
+use common::source_file::SourceFile;
+use common::source_map::SourceMap;
+use common::warnings::WarningOptions;
+use crate::arguments::ErrorFormat;
+
+pub struct Emitter<'a> {
+    pub sources: &'a SourceMap,
+    pub format: ErrorFormat,
+    pub warnings: WarningOptions,
+    errors: Cell<usize>,
+    fixes: RefCell<Vec<FixIt>>,
+}
+
+impl<'a> Emitter<'a> {
+    pub fn new(sources: &'a SourceMap, format: ErrorFormat, warnings: WarningOptions) -> Emitter<'a> {
+        Emitter { sources, format, warnings, errors: Cell::new(0), fixes: RefCell::new(Vec::new()) }
+    }
+
+    // Drops disabled warnings and turns the rest into errors under -Werror.
+    pub fn emit(&self, diagnostic: &Diagnostic) {
+        let Some(diagnostic) = self.warnings.apply(diagnostic.clone()) else {
+            return;
+        };
+
+        if diagnostic.severity == Severity::Error {
+            self.errors.set(self.errors.get() + 1);
+        }
+
+        self.fixes.borrow_mut().extend(diagnostic.fixes.iter().cloned());
+
+        match self.format {
+            ErrorFormat::Human => {
+                let _ = diagnostic.eprint(self.sources);
+            }
+            ErrorFormat::Json => match self.sources.get(diagnostic.span.source()) {
+                Some(file) => eprintln!("{}", diagnostic.to_json(file)),
+                None => eprintln!("{}", diagnostic.message),
+            },
+        }
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.errors.get()
+    }
+
+    // Rewrites each file with the fix-its emitted for it so far, returning the files changed
+    // and how many fixes each got.
+    pub fn apply_fixes(&self) -> io::Result<Vec<(&'a SourceFile, usize)>> {
+        let fixes = self.fixes.take();
+        let mut applied = Vec::new();
+
+        for file in self.sources.files() {
+            let file_fixes: Vec<FixIt> = fixes.iter().filter(|fix| fix.span.source() == &file.id).cloned().collect();
+
+            if !file_fixes.is_empty() {
+                fs::write(&file.filename, apply_fixes(file.text(), &file_fixes))?;
+                applied.push((file, file_fixes.len()));
+            }
+        }
+
+        Ok(applied)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,95 @@
+use std::fs;
+use std::process::ExitCode;
+use argh::FromArgs;
+use common::source_file::{Id, SourceFile};
+use common::source_map::SourceMap;
+use common::warnings::WarningOptions;
+use formatter::formatter::{format, BraceStyle, FormatOptions};
+use crate::arguments::ErrorFormat;
+use crate::emitter::Emitter;
+
+#[derive(FromArgs)]
+#[argh(description = "Reformat C source files in place")]
+pub struct FmtArguments {
+    #[argh(switch, description = "don't write anything, list the files that would change and fail if there are any; exits with 1 when files would be reformatted and 2 when some can't be formatted")]
+    pub check: bool,
+
+    #[argh(option, default = "4", description = "spaces per indentation level (default 4)")]
+    pub indent_width: usize,
+
+    #[argh(option, default = "BraceStyle::SameLine", description = "where a function body's opening brace goes: same-line (default) or next-line")]
+    pub brace_style: BraceStyle,
+
+    #[argh(option, default = "80", description = "the line width long declarators are broken to fit (default 80)")]
+    pub line_width: usize,
+
+    #[argh(option, default = "ErrorFormat::Human", description = "how diagnostics are printed: human (default) or json, one object per line")]
+    pub error_format: ErrorFormat,
+
+    #[argh(positional, description = "the files to format")]
+    pub input: Vec<String>,
+}
+
+pub fn run(args: FmtArguments) -> ExitCode {
+    if args.input.is_empty() {
+        eprintln!("No input files.\nRun niamc fmt --help for more information.");
+        return ExitCode::FAILURE;
+    }
+
+    let options = FormatOptions { indent_width: args.indent_width, brace_style: args.brace_style, line_width: args.line_width };
+    let mut sources = SourceMap::new();
+
+    for input in &args.input {
+        match SourceFile::from_file(Id::Unit(input.clone()), input) {
+            Err(err) => {
+                println!("Couldn't read file '{}': {}", input, err);
+                return ExitCode::FAILURE;
+            },
+            Ok(file) => sources.add(file),
+        }
+    }
+
+    let emitter = Emitter::new(&sources, args.error_format, WarningOptions::default());
+    let mut succeeded = true;
+    // files left alone because they don't parse as written, told apart from files to reformat
+    let mut unformattable = false;
+
+    for file in sources.files() {
+        let text = file.text();
+
+        let formatted = match format(text, &file.id, &options) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                for error in &errors {
+                    emitter.emit(error);
+                }
+
+                println!("Cannot format '{}': it doesn't parse as written", file.filename);
+                unformattable = true;
+                continue;
+            }
+        };
+
+        if formatted == text {
+            continue;
+        }
+
+        if args.check {
+            println!("Would reformat '{}'", file.filename);
+            succeeded = false;
+        } else if let Err(err) = fs::write(&file.filename, formatted) {
+            println!("Couldn't write file '{}': {}", file.filename, err);
+            succeeded = false;
+        } else {
+            println!("Formatted '{}'", file.filename);
+        }
+    }
+
+    if unformattable {
+        ExitCode::from(2)
+    } else if succeeded {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,33 @@
+use std::process::ExitCode;
+use argh::FromArgs;
+use common::source_file::{Id, SourceFile};
+use highlighter::highlighter::{highlight, OutputFormat};
+
+#[derive(FromArgs)]
+#[argh(description = "Print C source files with their tokens classified, for code review tools")]
+pub struct HighlightArguments {
+    #[argh(option, default = "OutputFormat::Ansi", description = "ansi (default) for terminal colors, or html for spans with a CSS class per token")]
+    pub format: OutputFormat,
+
+    #[argh(positional, description = "the files to highlight")]
+    pub input: Vec<String>,
+}
+
+pub fn run(args: HighlightArguments) -> ExitCode {
+    if args.input.is_empty() {
+        eprintln!("No input files.\nRun niamc highlight --help for more information.");
+        return ExitCode::FAILURE;
+    }
+
+    for input in &args.input {
+        match SourceFile::from_file(Id::Unit(input.clone()), input) {
+            Err(err) => {
+                println!("Couldn't read file '{}': {}", input, err);
+                return ExitCode::FAILURE;
+            },
+            Ok(file) => print!("{}", highlight(file.text(), args.format)),
+        }
+    }
+
+    ExitCode::SUCCESS
+}
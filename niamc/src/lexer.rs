@@ -1,64 +1,32 @@
-use ariadne::{Color, Label, Report, ReportKind};
 use common::source_file::SourceFile;
-use lexer::lexer::{Lexer, LexerError};
+use lexer::lexer::Lexer;
 use lexer::tokens::Token;
+use preprocessor::preprocessor::Preprocessed;
+use crate::Arguments;
+use crate::emitter::Emitter;
 
-pub fn run_lexer(file: &SourceFile, explain: bool) -> Option<Vec<Token>> {
+pub fn run_lexer(file: &SourceFile, preprocessed: &Preprocessed, emitter: &Emitter, args: &Arguments) -> Option<Vec<Token>> {
     println!("Lexing '{}'", file.filename);
 
-    let mut lexer = Lexer::new(&file.source.text());
+    let mut lexer = Lexer::new_with_unit(&preprocessed.text, preprocessed.map.output.clone())
+        .with_nested_comments(args.nested_comments);
+    let error_count = emitter.error_count();
     let (tokens, errors) = lexer.run();
 
-    if tokens.len() > 0 && explain {
+    if !tokens.is_empty() && args.explain {
        for token in &tokens {
-           println!("{}", token.explain(&file.source.text()));
+           println!("{}", token.explain(&preprocessed.text));
        }
     }
 
-    if errors.len() > 0 {
-        for error in lexer.errors {
-            match error {
-                LexerError::UnexpectedEofInsideComment(span) => {
-                    Report::build(ReportKind::Error, span.clone())
-                        .with_code("L001")
-                        .with_message("Comment started but not finished before end of file.")
-                        .with_label(Label::new(span)
-                            .with_message("Comment starts here")
-                            .with_color(Color::Primary))
-                        .finish()
-                        .eprint(file)
-                        .unwrap();
-                },
-                LexerError::NestedComment(comment_start, nested_comment_start) => {
-                    Report::build(ReportKind::Error, comment_start.clone())
-                        .with_code("L002")
-                        .with_message("Unknown token.")
-                        .with_label(Label::new(comment_start)
-                            .with_message("Comment starts here")
-                            .with_color(Color::Primary))
-                        .with_label(Label::new(nested_comment_start)
-                            .with_message("Nested comment starts here")
-                            .with_color(Color::Primary))
-                        .finish()
-                        .eprint(file)
-                        .unwrap();
-                },
-                LexerError::UnknownToken(span) => {
-                    Report::build(ReportKind::Error, span.clone())
-                        .with_code("L003")
-                        .with_message("Unknown token.")
-                        .with_label(Label::new(span)
-                            .with_message("Unknown token")
-                            .with_color(Color::Primary))
-                        .finish()
-                        .eprint(file)
-                        .unwrap();
-                },
-            }
-        }
+    for error in errors {
+        emitter.emit(&preprocessed.map.map_diagnostic(error.to_diagnostic()));
+    }
 
+    // warnings promoted by -Werror fail the run as well
+    if emitter.error_count() > error_count {
         return None;
     }
 
     Some(tokens)
-}
\ No newline at end of file
+}
@@ -1,32 +1,83 @@
 // never in a million c's
 
+use std::path::PathBuf;
 use std::process::ExitCode;
 use argh::FromArgs;
+use common::error_codes::ErrorCode;
 use common::source_file::{Id, SourceFile};
+use common::source_map::SourceMap;
+use common::warnings::WarningOptions;
+use preprocessor::preprocessor::{preprocess, Preprocessed, PreprocessorOptions};
+use crate::arguments::ErrorFormat;
+use crate::emitter::Emitter;
 use crate::lexer::run_lexer;
 use crate::parser::run_parser;
 
+mod arguments;
+mod emitter;
+mod fmt;
+mod highlight;
 mod lexer;
 mod parser;
 
 #[derive(FromArgs)]
-#[argh(description = "Minimal C Compiler")]
+#[argh(description = "Minimal C Compiler", note = "Run `niamc fmt --help` for the formatter and `niamc highlight --help` for the highlighter.")]
 pub struct Arguments {
-    #[argh(switch, short = 'e', description = "enable debug logging")]
+    #[argh(switch, short = 'e', description = "enable debug logging, or explain the diagnostic code given as input (e.g. --explain L002)")]
     pub explain: bool,
 
+    #[argh(switch, short = 'E', description = "preprocess the input file and print the result")]
+    pub preprocess: bool,
+
     #[argh(switch, description = "lex the input file")]
     pub lex: bool,
 
     #[argh(switch, description = "parse the input file")]
     pub parse: bool,
 
-    #[argh(positional, description = "the file to read")]
-    pub input: String,
+    #[argh(switch, description = "apply the suggested fixes to the input file in place")]
+    pub fix: bool,
+
+    #[argh(switch, description = "let block comments nest, so every /* needs its own */ (not standard C)")]
+    pub nested_comments: bool,
+
+    #[argh(switch, description = "replace trigraphs such as ??= and ??/ before preprocessing")]
+    pub trigraphs: bool,
+
+    #[argh(option, default = "ErrorFormat::Human", description = "how diagnostics are printed: human (default) or json, one object per line")]
+    pub error_format: ErrorFormat,
+
+    #[argh(option, short = 'W', description = "enable a warning (-Wname), disable it (-Wno-name), enable all (-Wall) or treat warnings as errors (-Werror)")]
+    pub warning: Vec<String>,
+
+    #[argh(option, short = 'I', description = "add a directory to search for included headers (-Idir)")]
+    pub include_dir: Vec<String>,
+
+    #[argh(option, short = 'D', description = "define a macro, as -DNAME or -DNAME=value")]
+    pub define: Vec<String>,
+
+    #[argh(positional, description = "the files to read")]
+    pub input: Vec<String>,
 }
 
 fn main() -> ExitCode {
-    let mut args: Arguments = argh::from_env();
+    match std::env::args().nth(1).as_deref() {
+        Some("fmt") => return fmt::run(arguments::subcommand_from_env("fmt")),
+        Some("highlight") => return highlight::run(arguments::subcommand_from_env("highlight")),
+        _ => {}
+    }
+
+    let mut args: Arguments = arguments::from_env();
+
+    if args.explain && let [input] = &args.input[..] && let Some(code) = ErrorCode::from_code(input) {
+        print!("{}", code.explanation());
+        return ExitCode::SUCCESS;
+    }
+
+    if args.input.is_empty() {
+        eprintln!("No input files.\nRun niamc --help for more information.");
+        return ExitCode::FAILURE;
+    }
 
     if !args.lex && !args.parse {
         args.parse = true;
@@ -36,47 +87,122 @@ fn main() -> ExitCode {
         args.lex = false;
     }
 
-    if args.lex {
-        let mut main = match SourceFile::from_file(Id::Main, &args.input) {
+    let warnings = match WarningOptions::from_flags(&args.warning) {
+        Ok(warnings) => warnings,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let options = PreprocessorOptions {
+        include_paths: args.include_dir.iter().map(PathBuf::from).collect(),
+        defines: args.define.iter()
+            .map(|define| match define.split_once('=') {
+                Some((name, value)) => (name.to_string(), value.to_string()),
+                None => (define.clone(), "1".to_string()),
+            })
+            .collect(),
+        nested_comments: args.nested_comments,
+        trigraphs: args.trigraphs,
+    };
+
+    // every file gets its own unit so spans from different files can't be confused
+    let mut inputs = Vec::new();
+
+    for input in &args.input {
+        match SourceFile::from_file(Id::Unit(input.clone()), input) {
             Err(err) => {
-                println!("Couldn't read file: {}", err);
+                println!("Couldn't read file '{}': {}", input, err);
                 return ExitCode::FAILURE;
             },
-            Ok(main) => main,
-        };
+            Ok(file) => inputs.push(file),
+        }
+    }
 
-        return match run_lexer(&mut main, args.explain) {
-            Some(_) => {
-                println!("Lexer lexed successfully");
-                ExitCode::SUCCESS
-            },
-            None => ExitCode::FAILURE
+    // preprocess everything first, so the headers are known before any diagnostic is shown
+    let mut preprocessed: Vec<Preprocessed> = inputs.iter().map(|file| preprocess(file, &options)).collect();
+    let ids: Vec<Id> = inputs.iter().map(|file| file.id.clone()).collect();
+    let mut sources = SourceMap::new();
+
+    for file in inputs {
+        sources.add(file);
+    }
+
+    for result in &mut preprocessed {
+        for header in std::mem::take(&mut result.included) {
+            if sources.get(&header.id).is_none() {
+                sources.add(header);
+            }
         }
     }
 
-    if args.parse {
-        let mut main = match SourceFile::from_file(Id::Main, &args.input) {
+    let emitter = Emitter::new(&sources, args.error_format, warnings);
+    let mut succeeded = true;
+
+    for (id, result) in ids.iter().zip(&preprocessed) {
+        let file = sources.get(id).expect("every input was added");
+        succeeded &= compile(file, result, &emitter, &args);
+    }
+
+    if args.fix {
+        match emitter.apply_fixes() {
+            Ok(applied) => {
+                for (file, count) in applied {
+                    println!("Applied {} fix(es) to '{}'", count, file.filename);
+                }
+            },
             Err(err) => {
-                println!("Couldn't read file: {}", err);
+                println!("Couldn't write file: {}", err);
                 return ExitCode::FAILURE;
-            },
-            Ok(main) => main,
-        };
+            }
+        }
+    }
 
-        return match run_lexer(&mut main, args.explain) {
-            Some(tokens) => {
-                println!("Lexer lexed successfully");
+    if succeeded {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
 
-                if let Some(_) = run_parser(&mut main, tokens, args.explain) {
-                    println!("Parsed successfully");
-                    return ExitCode::SUCCESS;
-                }
+fn compile(file: &SourceFile, preprocessed: &Preprocessed, emitter: &Emitter, args: &Arguments) -> bool {
+    let error_count = emitter.error_count();
+
+    for error in &preprocessed.errors {
+        emitter.emit(&error.to_diagnostic());
+    }
 
-                ExitCode::FAILURE
+    if emitter.error_count() > error_count {
+        return false;
+    }
+
+    if args.preprocess {
+        print!("{}", preprocessed.text);
+        return true;
+    }
+
+    if args.lex {
+        return match run_lexer(file, preprocessed, emitter, args) {
+            Some(_) => {
+                println!("Lexer lexed successfully");
+                true
             },
-            None => ExitCode::FAILURE
+            None => false
         }
     }
 
-    ExitCode::SUCCESS
+    match run_lexer(file, preprocessed, emitter, args) {
+        Some(tokens) => {
+            println!("Lexer lexed successfully");
+
+            if run_parser(file, preprocessed, tokens, emitter, args.explain).is_some() {
+                println!("Parsed successfully");
+                true
+            } else {
+                false
+            }
+        },
+        None => false
+    }
 }
@@ -1,10 +1,11 @@
-use ariadne::{Color, Label, Report, ReportKind};
 use common::source_file::SourceFile;
+use common::span::Span;
 use lexer::tokens::Token;
 use parser::ast::Program;
-use parser::errors::ParseError;
 use parser::parser::Parser;
 use parser::token_collection::TokenCollection;
+use preprocessor::preprocessor::Preprocessed;
+use crate::emitter::Emitter;
 
 /* T-Dark, Speedrun World Champion — 1:37 AM
 In an ideal world, if parsing fails it doesn't consume any tokens so you don't need to backtrack, but in more complex cases backtracking is admittedly required
@@ -13,53 +14,29 @@ I usually implement this by parsing in terms of a "cursor" which is just a slice
  */
 
 #[allow(unused)]
-pub fn run_parser(file: &SourceFile, tokens: Vec<Token>, explain: bool) -> Option<Program> {
+pub fn run_parser(file: &SourceFile, preprocessed: &Preprocessed, tokens: Vec<Token>, emitter: &Emitter, explain: bool) -> Option<Program> {
     println!("Parsing '{}'", file.filename);
 
+    let error_count = emitter.error_count();
     let token_col = TokenCollection::new(tokens);
 
-    let mut parser = Parser::new(token_col, &file.source.text());
+    let mut parser = Parser::new(token_col, &preprocessed.text);
 
-    let parsed = parser.run();
+    let (program, errors) = parser.run();
 
-    match parsed {
-        Ok(program) => {
-            if explain {
-                println!("{:#?}", program);
-            }
+    if explain {
+        print!("{}", program);
+    }
+
+    let eof = Span::new_with_unit(preprocessed.text.len(), 0, preprocessed.map.output.clone());
+
+    for error in &errors {
+        emitter.emit(&preprocessed.map.map_diagnostic(error.to_diagnostic(eof.clone())));
+    }
 
-            Some(program)
-        }
-        Err(ParseError::UnexpectedEOF(token)) => {
-            let _  = Report::build(ReportKind::Error, file.eof())
-                .with_message(format!("Unexpected end of file, expected token '{:?}'", token))
-                .finish()
-                .print(file);
-            None
-        },
-        Err(ParseError::SyntaxError(found, wanted)) => {
-            let _ = Report::build(ReportKind::Error, found.span)
-                .with_message(format!("Syntax error. Expected token '{:?}'", wanted))
-                .finish()
-                .print(file);
-            None
-        },
-        Err(ParseError::InvalidNumber(found, error)) => {
-            let _ = Report::build(ReportKind::Error, found.span)
-                .with_message(format!("Invalid constant: '{:?}'", error))
-                .finish()
-                .print(file);
-            None
-        },
-        Err(ParseError::ExpectingEOF(token)) => {
-            let _ = Report::build(ReportKind::Error, token.span.clone())
-                .with_message(format!("Expecting EOF"))
-                .with_label(Label::new(token.span)
-                    .with_message("Unexpected token here.")
-                    .with_color(Color::Primary))
-                .finish()
-                .print(file);
-            None
-        }
+    if emitter.error_count() == error_count {
+        Some(program)
+    } else {
+        None
     }
 }
\ No newline at end of file
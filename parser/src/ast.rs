@@ -1,20 +1,209 @@
+use std::fmt;
 
-#[derive(Debug)]
+// Integer types as in LP64: `int` is 32 bits, `long` and `long long` are 64.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum IntegerType {
+    Int,
+    UnsignedInt,
+    Long,
+    UnsignedLong,
+    LongLong,
+    UnsignedLongLong,
+}
+
+impl IntegerType {
+    pub fn max(self) -> u64 {
+        match self {
+            IntegerType::Int => i32::MAX as u64,
+            IntegerType::UnsignedInt => u32::MAX as u64,
+            IntegerType::Long | IntegerType::LongLong => i64::MAX as u64,
+            IntegerType::UnsignedLong | IntegerType::UnsignedLongLong => u64::MAX,
+        }
+    }
+}
+
+impl fmt::Display for IntegerType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IntegerType::Int => "int",
+            IntegerType::UnsignedInt => "unsigned int",
+            IntegerType::Long => "long",
+            IntegerType::UnsignedLong => "unsigned long",
+            IntegerType::LongLong => "long long",
+            IntegerType::UnsignedLongLong => "unsigned long long",
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct IntegerConstant {
+    pub value: u64,
+    // picked from the value and suffix by the C rules
+    pub ty: IntegerType,
+}
+
+// The AST prints as C source that parses back to the same tree, in the formatter's layout.
+// Nodes that failed to parse have no source and print as comments.
+
+// With the suffix that gives the constant its type again, whatever it was written as.
+impl fmt::Display for IntegerConstant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let suffix = match self.ty {
+            IntegerType::Int => "",
+            IntegerType::UnsignedInt => "U",
+            IntegerType::Long => "L",
+            IntegerType::UnsignedLong => "UL",
+            IntegerType::LongLong => "LL",
+            IntegerType::UnsignedLongLong => "ULL",
+        };
+
+        write!(f, "{}{}", self.value, suffix)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
 pub enum Expression {
-    Constant(i64),
+    Constant(IntegerConstant),
 }
 
-#[derive(Debug)]
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::Constant(constant) => write!(f, "{}", constant),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
 pub enum Statement {
     Return(Expression),
+    /// A statement that failed to parse; the error has been recorded by the parser.
+    Error,
 }
 
-#[derive(Debug)]
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Return(expression) => write!(f, "return {};", expression),
+            Statement::Error => f.write_str("/* statement with errors */"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
 pub enum FunctionDefinition {
-    Function { name: String, body: Statement }
+    Function { name: String, body: Statement },
+    /// A function whose declaration failed to parse; its body is still parsed for errors.
+    Error,
+}
+
+impl fmt::Display for FunctionDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FunctionDefinition::Function { name, body } => write!(f, "int {}(void) {{\n    {}\n}}\n", name, body),
+            FunctionDefinition::Error => f.write_str("/* function with errors */\n"),
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct Program {
     pub function: FunctionDefinition,
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.function)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use lexer::lexer::Lexer;
+    use crate::errors::ParseError;
+    use crate::parser::Parser;
+    use crate::token_collection::TokenCollection;
+    use super::*;
+
+    fn parse(text: &str) -> (Program, Vec<ParseError>) {
+        let (tokens, errors) = Lexer::new(text).run();
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        Parser::new(TokenCollection::new(tokens), text).run()
+    }
+
+    // Parses `text`, prints the tree and parses that: both trees are the same, and printing
+    // the second gives the same text again.
+    fn round_trip(text: &str) {
+        let (program, errors) = parse(text);
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let printed = program.to_string();
+        let (reparsed, errors) = parse(&printed);
+
+        assert!(errors.is_empty(), "{:?} in\n{}", errors, printed);
+        assert_eq!(reparsed, program, "printed as\n{}", printed);
+        assert_eq!(reparsed.to_string(), printed);
+    }
+
+    #[test]
+    fn prints_c() {
+        let (program, _) = parse("int main(void){return 0x1F;}");
+        assert_eq!(program.to_string(), "int main(void) {\n    return 31;\n}\n");
+
+        let (program, _) = parse("int main(void) { return 0 }");
+        assert_eq!(program.to_string(), "int main(void) {\n    /* statement with errors */\n}\n");
+    }
+
+    #[test]
+    fn constants_keep_their_type() {
+        for text in ["0", "2147483647", "2147483648", "0xFFFFFFFF", "0xFFFFFFFFFFFFFFFF", "07u", "1l", "1ul", "1LL", "1uLL", "0b101'1"] {
+            round_trip(&format!("int main(void) {{ return {}; }}", text));
+        }
+    }
+
+    // Every valid program of the compiler test suite that this parser handles. The suite is a
+    // submodule, so this only runs when asked for: `git submodule update --init`, then
+    // `cargo test -p parser -- --ignored`, as `test.zsh` does.
+    #[test]
+    #[ignore = "needs the writing-a-c-compiler-tests submodule"]
+    fn test_suite_round_trips() {
+        let suite = Path::new(env!("CARGO_MANIFEST_DIR")).join("../writing-a-c-compiler-tests/tests");
+        let chapters = fs::read_dir(&suite)
+            .unwrap_or_else(|err| panic!("{} is not checked out ({}), run `git submodule update --init`", suite.display(), err));
+
+        let mut directories: Vec<_> = chapters.flatten().map(|chapter| chapter.path().join("valid")).collect();
+        let mut round_tripped = 0;
+
+        // later chapters keep some programs in subdirectories of `valid`
+        while let Some(directory) = directories.pop() {
+            let Ok(entries) = fs::read_dir(&directory) else {
+                continue;
+            };
+
+            for path in entries.flatten().map(|entry| entry.path()) {
+                if path.is_dir() {
+                    directories.push(path);
+                    continue;
+                }
+
+                if path.extension().is_none_or(|extension| extension != "c") {
+                    continue;
+                }
+
+                let text = fs::read_to_string(&path).unwrap();
+                let (tokens, lexer_errors) = Lexer::new(&text).run();
+
+                if lexer_errors.is_empty() && Parser::new(TokenCollection::new(tokens), &text).run().1.is_empty() {
+                    round_trip(&text);
+                    round_tripped += 1;
+                }
+            }
+        }
+
+        // an empty checkout would otherwise pass without testing anything
+        assert!(round_tripped > 0, "no program of {} round-tripped", suite.display());
+    }
 }
\ No newline at end of file
@@ -1,42 +1,109 @@
-use std::num::{IntErrorKind, ParseIntError};
+use common::diagnostics::{Diagnostic, FixIt};
+use common::error_codes::ErrorCode;
+use common::span::Span;
 use lexer::tokens::{Token, TokenKind};
+use crate::ast::IntegerType;
 
 #[derive(Debug, PartialEq, Clone, Eq)]
 #[allow(dead_code)]
 pub enum ParseError {
-    SyntaxError(Token, TokenKind),
-    UnexpectedEOF(TokenKind),
-    InvalidNumber(Token, IntParseError),
+    SyntaxError(Box<Token>, Vec<TokenKind>, Option<Span>), // found, any of wanted, previous token
+    UnexpectedEOF(Vec<TokenKind>, Option<Span>), // any of wanted, last token
+    InvalidNumber(Box<Token>, IntParseError),
     ExpectingEOF(Token),
 }
 
-#[derive(Debug, PartialEq, Clone, Copy, Eq)]
-#[allow(dead_code)]
+impl ParseError {
+    // `eof` is where errors about running out of tokens are reported.
+    pub fn to_diagnostic(&self, eof: Span) -> Diagnostic {
+        match self {
+            ParseError::UnexpectedEOF(wanted, previous) => {
+                let diagnostic = Diagnostic::error(eof.clone(), format!("expected {} but found end of file", expected_list(wanted)))
+                    .with_code(ErrorCode::UnexpectedEof)
+                    .with_label(eof, "file ends here");
+
+                with_terminator_fix(diagnostic, wanted, previous)
+            }
+            ParseError::SyntaxError(found, wanted, previous) => {
+                let diagnostic = Diagnostic::error(found.span.clone(), format!("expected {} but found {}", expected_list(wanted), found.kind))
+                    .with_code(ErrorCode::UnexpectedToken)
+                    .with_label(found.span.clone(), format!("unexpected {}", found.kind));
+
+                with_terminator_fix(diagnostic, wanted, previous)
+            }
+            ParseError::InvalidNumber(_, error) => error.to_diagnostic().with_code(ErrorCode::InvalidConstant),
+            ParseError::ExpectingEOF(token) =>
+                Diagnostic::error(token.span.clone(), format!("expected end of file but found {}", token.kind))
+                    .with_code(ErrorCode::ExpectedEof)
+                    .with_label(token.span.clone(), "Unexpected token here."),
+        }
+    }
+}
+
+// A missing terminator is almost always meant right after the previous token.
+fn with_terminator_fix(diagnostic: Diagnostic, wanted: &[TokenKind], previous: &Option<Span>) -> Diagnostic {
+    match (wanted, previous) {
+        ([TokenKind::Semicolon], Some(previous)) => diagnostic.with_fix(FixIt::insert(previous.after(), ";")),
+        ([TokenKind::CloseParen], Some(previous)) => diagnostic.with_fix(FixIt::insert(previous.after(), ")")),
+        _ => diagnostic,
+    }
+}
+
+// "';'", "';' or ','", "'(', ';' or ','"
+fn expected_list(kinds: &[TokenKind]) -> String {
+    match kinds {
+        [] => "nothing".to_string(),
+        [kind] => kind.to_string(),
+        [rest @ .., last] => {
+            let rest: Vec<String> = rest.iter().map(|kind| kind.to_string()).collect();
+            format!("{} or {}", rest.join(", "), last)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Eq)]
 pub enum IntParseError {
-    Empty,
-    /// Contains an invalid digit in its context.
-    ///
-    /// Among other causes, this variant will be constructed when parsing a string that
-    /// contains a non-ASCII char.
-    ///
-    /// This variant is also constructed when a `+` or `-` is misplaced within a string
-    /// either on its own or in the middle of a number.
-    InvalidDigit,
-    /// Integer is too large to store in target integer type.
-    PosOverflow,
-    /// Integer is too small to store in target integer type.
-    NegOverflow,
-    Unknown
+    // a digit too large for the constant's base, with the base
+    InvalidDigit(Span, char, u32),
+    InvalidSuffix(Span, String),
+    // `0x` or `0b` with nothing after it
+    MissingDigits(Span),
+    // a `'` that is not between two digits
+    MisplacedSeparator(Span),
+    FloatingConstant(Span),
+    // the value fits none of the types its suffix allows; the last of those types
+    TooLarge(Span, IntegerType),
 }
 
-impl From<ParseIntError> for IntParseError {
-    fn from(err: ParseIntError) -> IntParseError {
-        match err.kind() {
-            IntErrorKind::PosOverflow => IntParseError::PosOverflow,
-            IntErrorKind::NegOverflow => IntParseError::NegOverflow,
-            IntErrorKind::Empty => IntParseError::Empty,
-            IntErrorKind::InvalidDigit => IntParseError::InvalidDigit,
-            _ => IntParseError::PosOverflow
+impl IntParseError {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        match self {
+            IntParseError::InvalidDigit(span, digit, base) => {
+                let base = match base {
+                    2 => "binary",
+                    8 => "octal",
+                    16 => "hexadecimal",
+                    _ => "decimal",
+                };
+
+                Diagnostic::error(span.clone(), format!("invalid digit '{}' in {} constant", digit, base))
+                    .with_label(span.clone(), format!("not a valid {} digit", base))
+            }
+            IntParseError::InvalidSuffix(span, suffix) =>
+                Diagnostic::error(span.clone(), format!("invalid suffix '{}' on integer constant", suffix))
+                    .with_label(span.clone(), "expected u, l, ll or a combination of u with one of them"),
+            IntParseError::MissingDigits(span) =>
+                Diagnostic::error(span.clone(), "integer constant has no digits")
+                    .with_label(span.clone(), "digits must follow this prefix"),
+            IntParseError::MisplacedSeparator(span) =>
+                Diagnostic::error(span.clone(), "digit separator must be between two digits")
+                    .with_label(span.clone(), "misplaced separator"),
+            IntParseError::FloatingConstant(span) =>
+                Diagnostic::error(span.clone(), "floating constants are not supported")
+                    .with_label(span.clone(), "floating constant"),
+            IntParseError::TooLarge(span, ty) =>
+                Diagnostic::error(span.clone(), format!("integer constant is too large for type '{}'", ty))
+                    .with_label(span.clone(), "too large"),
         }
     }
 }
@@ -3,3 +3,4 @@ pub mod parser;
 pub mod ast;
 pub mod utilities;
 pub mod errors;
+pub mod syntax;
@@ -1,74 +1,532 @@
+use std::ops::Range;
+use std::sync::Arc;
+use common::source_file::TextEdit;
 use common::span::Span;
-use lexer::tokens::{TokenKind};
+use lexer::tokens::{Token, TokenKind};
 use crate::ast::{Expression, FunctionDefinition, Program, Statement};
 use crate::errors::ParseError;
+use crate::syntax::{GreenBuilder, GreenNode, NodeKind, SyntaxElement, SyntaxNode};
 use crate::token_collection::{TokenCollection};
 use crate::utilities::parse_number;
 
+// Where a syntax tree node starts or ends, as an index into the parser's tokens.
+#[derive(Debug)]
+enum Event {
+    Start(NodeKind, usize),
+    Finish(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Checkpoint {
+    events: usize,
+    index: usize,
+}
+
 pub struct Parser<'a> {
     tokens: TokenCollection,
     contents: &'a str,
+    pub errors: Vec<ParseError>,
+    events: Vec<Event>,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(tokens: TokenCollection, contents: &'a str) -> Parser<'a> {
-        Parser { tokens, contents }
+        Parser { tokens, contents, errors: Vec::new(), events: Vec::new() }
     }
 
     pub fn get_text(&self, span: &Span) -> &'a str {
         &self.contents[span.range()]
     }
 
-    pub fn run(&mut self) -> Result<Program, ParseError> {
-        self.parse_program()
+    // Always produces a program; parts that failed to parse are error nodes and
+    // every syntax error found along the way is returned alongside it.
+    pub fn run(&mut self) -> (Program, Vec<ParseError>) {
+        let program = self.parse_program();
+
+        (program, self.errors.clone())
+    }
+
+    // The concrete syntax tree of what `run` parsed. Given the lexer's trivia tokens it holds
+    // every byte of the source, so printing it gives back the text unchanged. Trivia in front
+    // of a node's first token belongs to the enclosing node.
+    pub fn syntax_tree(&self) -> SyntaxNode {
+        let mut builder = GreenBuilder::new();
+        let mut all = self.tokens.all.iter().peekable();
+        let mut parsed = 0;
+
+        // Adds tokens until `index` parser tokens are in, and then the trivia after them if
+        // `trailing` is set.
+        let mut add_until = |builder: &mut GreenBuilder, index: usize, trailing: bool| {
+            while let Some(token) = all.peek() {
+                if token.kind.is_trivia() {
+                    if parsed == index && !trailing {
+                        break;
+                    }
+                } else if parsed == index {
+                    break;
+                } else {
+                    parsed += 1;
+                }
+
+                builder.token(token.kind, &self.contents[token.span.range()]);
+                all.next();
+            }
+        };
+
+        builder.start_node(NodeKind::TranslationUnit);
+
+        for event in &self.events {
+            match *event {
+                Event::Start(kind, index) => {
+                    add_until(&mut builder, index, true);
+                    builder.start_node(kind);
+                }
+                Event::Finish(index) => {
+                    add_until(&mut builder, index, false);
+                    builder.finish_node();
+                }
+            }
+        }
+
+        add_until(&mut builder, usize::MAX, true);
+        builder.finish_node();
+
+        SyntaxNode::new_root(builder.finish())
+    }
+
+    // Brings `tree` up to date with `text`, the text it was built from with `edit` applied.
+    // `tokens` are those of `text` with trivia, as `Lexer::relex` gives them along with the
+    // `relexed` range. Only the function definition the changes fall in is parsed again and the
+    // rest of the old tree is shared. Gives None when the changes reach outside one definition
+    // or it no longer parses cleanly, as then its extent can depend on what follows it: the
+    // whole text has to be parsed again.
+    pub fn reparse(tree: &SyntaxNode, tokens: &[Token], relexed: Range<usize>, edit: &TextEdit, text: &str) -> Option<SyntaxNode> {
+        // what changed, in the edited text
+        let changed = match &tokens[relexed] {
+            [] => edit.range.start..edit.new_end(),
+            [first, .., last] | [first @ last] =>
+                first.span.range().start.min(edit.range.start)..last.span.range().end.max(edit.new_end()),
+        };
+
+        let (index, definition) = tree.children_with_tokens().enumerate().find_map(|(index, child)| match child {
+            SyntaxElement::Node(node) if node.kind() == NodeKind::FunctionDefinition
+                && node.range().start <= changed.start && edit.unshift(changed.end) <= node.range().end => Some((index, node)),
+            _ => None,
+        })?;
+
+        let range = definition.range().start..edit.shift(definition.range().end);
+        let first = tokens.partition_point(|token| token.span.range().start < range.start);
+        let last = tokens.partition_point(|token| token.span.range().start < range.end);
+
+        if tokens.get(first)?.span.range().start != range.start || tokens.get(last.checked_sub(1)?)?.span.range().end != range.end {
+            return None;
+        }
+
+        let mut parser = Parser::new(TokenCollection::new(tokens[first..last].to_vec()), text);
+        parser.parse_function();
+
+        if !parser.errors.is_empty() || parser.tokens.peek().is_some() {
+            return None;
+        }
+
+        let reparsed = parser.syntax_tree();
+        let mut children = tree.green().children.clone();
+        children.splice(index..index + 1, reparsed.green().children.iter().cloned());
+
+        Some(SyntaxNode::new_root(Arc::new(GreenNode::new(NodeKind::TranslationUnit, children))))
+    }
+
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint { events: self.events.len(), index: self.tokens.index }
+    }
+
+    // Makes the tokens taken since `checkpoint` a node of the syntax tree, if there are any.
+    fn wrap(&mut self, checkpoint: Checkpoint, kind: NodeKind) {
+        if self.tokens.index == checkpoint.index {
+            return;
+        }
+
+        self.events.insert(checkpoint.events, Event::Start(kind, checkpoint.index));
+        self.events.push(Event::Finish(self.tokens.index));
+    }
+
+    // Once the end of file has been hit every enclosing construct would report it
+    // again, so only the first one is kept.
+    fn add_error(&mut self, error: ParseError) {
+        if matches!(error, ParseError::UnexpectedEOF(..)) && matches!(self.errors.last(), Some(ParseError::UnexpectedEOF(..))) {
+            return;
+        }
+
+        self.errors.push(error);
+    }
+
+    // Panic mode recovery: skip past the next ';', or up to (but not over) the next '}'
+    // so that the enclosing block can still be closed.
+    fn synchronize(&mut self) {
+        while let Some(token) = self.tokens.peek() {
+            match token.kind {
+                TokenKind::Semicolon => {
+                    self.tokens.take_token();
+                    break;
+                }
+                TokenKind::CloseBrace => break,
+                _ => {
+                    self.tokens.take_token();
+                }
+            }
+        }
+    }
+
+    // Recovery for a broken function declarator: skip to the body if there is one.
+    fn synchronize_to_body(&mut self) {
+        while let Some(token) = self.tokens.peek() {
+            match token.kind {
+                TokenKind::OpenBrace => break,
+                TokenKind::Semicolon | TokenKind::CloseBrace => {
+                    self.tokens.take_token();
+                    break;
+                }
+                _ => {
+                    self.tokens.take_token();
+                }
+            }
+        }
     }
 }
 
 trait CeeParser {
-    fn parse_statement(&mut self) -> Result<Statement, ParseError>;
+    fn parse_statement(&mut self) -> Statement;
+    fn parse_return_statement(&mut self) -> Result<Statement, ParseError>;
     fn parse_expression(&mut self) -> Result<Expression, ParseError>;
-    fn parse_function(&mut self) -> Result<FunctionDefinition, ParseError>;
-    fn parse_program(&mut self) -> Result<Program, ParseError>;
+    fn parse_function_declarator(&mut self) -> Result<String, ParseError>;
+    fn parse_function_body(&mut self) -> Statement;
+    fn parse_function(&mut self) -> FunctionDefinition;
+    fn parse_program(&mut self) -> Program;
 }
 
 impl<'a> CeeParser for Parser<'a> {
-    fn parse_statement(&mut self) -> Result<Statement, ParseError> {
+    fn parse_statement(&mut self) -> Statement {
+        let checkpoint = self.checkpoint();
+
+        match self.parse_return_statement() {
+            Ok(statement) => {
+                self.wrap(checkpoint, NodeKind::ReturnStatement);
+                statement
+            }
+            Err(error) => {
+                self.add_error(error);
+                self.synchronize();
+                self.wrap(checkpoint, NodeKind::Error);
+                Statement::Error
+            }
+        }
+    }
+
+    fn parse_return_statement(&mut self) -> Result<Statement, ParseError> {
         self.tokens.expect(TokenKind::ReturnKeyword)?;
 
         let expr = self.parse_expression()?;
 
         self.tokens.expect(TokenKind::Semicolon)?;
-        
+
         Ok(Statement::Return(expr))
     }
 
     fn parse_expression(&mut self) -> Result<Expression, ParseError> {
+        let checkpoint = self.checkpoint();
         let token = self.tokens.expect(TokenKind::Constant)?;
+        self.wrap(checkpoint, NodeKind::ConstantExpression);
+
         let text = self.get_text(&token.span);
         let num = parse_number(text, &token)?;
         Ok(Expression::Constant(num))
     }
-    
-    fn parse_function(&mut self) -> Result<FunctionDefinition, ParseError> {
+
+    fn parse_function_declarator(&mut self) -> Result<String, ParseError> {
+        let checkpoint = self.checkpoint();
         self.tokens.expect(TokenKind::IntKeyword)?;
         let id = self.tokens.expect(TokenKind::Identifier)?;
         let text = self.get_text(&id.span);
         self.tokens.expect(TokenKind::OpenParen)?;
         self.tokens.expect(TokenKind::VoidKeyword)?;
         self.tokens.expect(TokenKind::CloseParen)?;
-        self.tokens.expect(TokenKind::OpenBrace)?;
-        let statements = self.parse_statement()?;
-        self.tokens.expect(TokenKind::CloseBrace)?;
-        Ok(FunctionDefinition::Function { name: text.to_string(), body: statements })
-    }
-    
-    fn parse_program(&mut self) -> Result<Program, ParseError> {
-        let function_def = self.parse_function()?;
-
-        if self.tokens.is_empty() {
-            Ok(Program { function: function_def })
-        } else {
-            Err(ParseError::ExpectingEOF(self.tokens.last().unwrap().clone()))
+        self.wrap(checkpoint, NodeKind::Declarator);
+        Ok(text.to_string())
+    }
+
+    fn parse_function_body(&mut self) -> Statement {
+        let checkpoint = self.checkpoint();
+
+        if let Err(error) = self.tokens.expect(TokenKind::OpenBrace) {
+            self.add_error(error);
+            self.synchronize();
+            self.wrap(checkpoint, NodeKind::Error);
+            return Statement::Error;
+        }
+
+        let statement = self.parse_statement();
+
+        if let Err(error) = self.tokens.expect(TokenKind::CloseBrace) {
+            self.add_error(error);
+            self.synchronize();
+            let _ = self.tokens.expect(TokenKind::CloseBrace);
+        }
+
+        self.wrap(checkpoint, NodeKind::CompoundStatement);
+        statement
+    }
+
+    fn parse_function(&mut self) -> FunctionDefinition {
+        let checkpoint = self.checkpoint();
+
+        let function = match self.parse_function_declarator() {
+            Ok(name) => {
+                let body = self.parse_function_body();
+                FunctionDefinition::Function { name, body }
+            }
+            Err(error) => {
+                self.add_error(error);
+                self.synchronize_to_body();
+                self.wrap(checkpoint, NodeKind::Error);
+
+                if self.tokens.peek().is_some_and(|token| token.kind == TokenKind::OpenBrace) {
+                    self.parse_function_body();
+                }
+
+                FunctionDefinition::Error
+            }
+        };
+
+        self.wrap(checkpoint, NodeKind::FunctionDefinition);
+        function
+    }
+
+    fn parse_program(&mut self) -> Program {
+        let function_def = self.parse_function();
+
+        if let Some(extra) = self.tokens.peek() {
+            // the first token past the function is the one that shouldn't be there
+            self.add_error(ParseError::ExpectingEOF(extra.clone()));
+
+            let checkpoint = self.checkpoint();
+            while self.tokens.take_token().is_some() {}
+            self.wrap(checkpoint, NodeKind::Error);
         }
+
+        Program { function: function_def }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use common::source_file::Id;
+    use lexer::lexer::Lexer;
+    use proptest::prelude::*;
+    use crate::ast::{IntegerConstant, IntegerType};
+    use crate::errors::IntParseError;
+    use super::*;
+
+    fn parse(text: &str) -> (Program, Vec<ParseError>) {
+        let (tokens, _) = Lexer::new(text).run();
+        let mut parser = Parser::new(TokenCollection::new(tokens), text);
+
+        parser.run()
+    }
+
+    fn syntax_tree(text: &str) -> SyntaxNode {
+        let (tokens, _) = Lexer::new(text).with_trivia(true).run();
+        let mut parser = Parser::new(TokenCollection::new(tokens), text);
+
+        parser.run();
+        parser.syntax_tree()
+    }
+
+    #[test]
+    fn syntax_tree_is_lossless() {
+        for text in [
+            "",
+            "int main(void) { return 2; }",
+            "  // leading\nint main(void) {\n    return 0x1F; /* done */\n}\n\n",
+            "int main(void { return 0 }",
+            "int main(void) { return 0 return 1; } trailing ;",
+            "int @ main(void) { \"unterminated\n return 1; }",
+            "int main(void) { return",
+        ] {
+            assert_eq!(syntax_tree(text).to_string(), text);
+        }
+    }
+
+    #[test]
+    fn syntax_tree_structure() {
+        let tree = syntax_tree("// f\nint main(void) {\n  return 2;\n}\n");
+
+        assert_eq!(format!("{:?}", tree), "\
+TranslationUnit@0..36
+  Comment@0..4 \"// f\"
+  Whitespace@4..5 \"\\n\"
+  FunctionDefinition@5..35
+    Declarator@5..19
+      IntKeyword@5..8 \"int\"
+      Whitespace@8..9 \" \"
+      Identifier@9..13 \"main\"
+      OpenParen@13..14 \"(\"
+      VoidKeyword@14..18 \"void\"
+      CloseParen@18..19 \")\"
+    Whitespace@19..20 \" \"
+    CompoundStatement@20..35
+      OpenBrace@20..21 \"{\"
+      Whitespace@21..24 \"\\n  \"
+      ReturnStatement@24..33
+        ReturnKeyword@24..30 \"return\"
+        Whitespace@30..31 \" \"
+        ConstantExpression@31..32
+          Constant@31..32 \"2\"
+        Semicolon@32..33 \";\"
+      Whitespace@33..34 \"\\n\"
+      CloseBrace@34..35 \"}\"
+  Whitespace@35..36 \"\\n\"
+");
+    }
+
+    // Reparsing after `edit` gives the tree parsing the edited text from scratch does, if it
+    // reparses at all.
+    fn check_reparse(tree: &SyntaxNode, text: &str, edit: &TextEdit) -> Option<SyntaxNode> {
+        let mut edited = text.to_string();
+        edited.replace_range(edit.range.clone(), &edit.replacement);
+
+        let (mut tokens, _) = Lexer::new(text).with_trivia(true).run();
+        let relexed = Lexer::new(&edited).with_trivia(true).relex(&mut tokens, edit);
+        let reparsed = Parser::reparse(tree, &tokens, relexed, edit, &edited)?;
+
+        assert_eq!(format!("{:?}", reparsed), format!("{:?}", syntax_tree(&edited)));
+        Some(reparsed)
+    }
+
+    #[test]
+    fn reparses_the_edited_definition() {
+        let text = "// f\nint main(void) {\n    return 1;\n}\nint other(void) {\n    return 2;\n}\n";
+        let one = text.find('1').unwrap();
+        let tree = syntax_tree(text);
+        let reparsed = check_reparse(&tree, text, &TextEdit::new(one..one + 1, "0x10")).unwrap();
+
+        // the definition after it is the same node as before
+        let tail = |tree: &SyntaxNode| tree.children().last().unwrap().green().clone();
+        assert!(Arc::ptr_eq(&tail(&tree), &tail(&reparsed)));
+
+        check_reparse(&tree, text, &TextEdit::new(one - 7..one - 1, "return")).unwrap();
+        check_reparse(&tree, text, &TextEdit::new(one + 2..one + 2, "\n\n    ")).unwrap();
+
+        // errors or changes outside the definition need the whole text parsed
+        assert!(check_reparse(&tree, text, &TextEdit::new(one..one + 1, "")).is_none());
+        assert!(check_reparse(&tree, text, &TextEdit::new(one + 4..one + 5, "")).is_none());
+        assert!(check_reparse(&tree, text, &TextEdit::new(text.len() - 4..text.len() - 4, "3")).is_none());
+        assert!(check_reparse(&tree, text, &TextEdit::new(0..2, "/*")).is_none());
+    }
+
+    proptest! {
+        #[test]
+        fn reparsing_matches_parsing_again(
+            start in 0usize..80,
+            len in 0usize..4,
+            replacement in prop::sample::select(vec!["", " ", "\n", "1", "x", ";", "}", "{", "return", "/*", "*/", "//"]),
+        ) {
+            let text = "// f\nint main(void) {\n    return 1;\n}\nint other(void) {\n    return 2;\n}\n";
+            let start = start.min(text.len());
+            let edit = TextEdit::new(start..(start + len).min(text.len()), replacement);
+
+            check_reparse(&syntax_tree(text), text, &edit);
+        }
+    }
+
+    #[test]
+    fn valid_program() {
+        let (program, errors) = parse("int main(void) { return 2; }");
+
+        assert_matches!(errors[..], []);
+        assert_matches!(program.function, FunctionDefinition::Function { ref name, body: Statement::Return(Expression::Constant(IntegerConstant { value: 2, ty: IntegerType::Int })) } if name == "main");
+    }
+
+    #[test]
+    fn recovers_inside_statement() {
+        let (program, errors) = parse("int main(void) { return 0 return 1; }");
+
+        assert_matches!(&errors[..], [ParseError::SyntaxError(token, wanted, _)] if token.kind == TokenKind::ReturnKeyword && wanted == &[TokenKind::Semicolon]);
+        assert_matches!(program.function, FunctionDefinition::Function { body: Statement::Error, .. });
+    }
+
+    #[test]
+    fn reports_errors_in_declarator_and_body() {
+        let (program, errors) = parse("int main(void { return 0 }");
+
+        assert_matches!(&errors[..], [
+            ParseError::SyntaxError(_, first, _),
+            ParseError::SyntaxError(found, second, _),
+        ] if first == &[TokenKind::CloseParen] && second == &[TokenKind::Semicolon] && found.kind == TokenKind::CloseBrace);
+        assert_matches!(program.function, FunctionDefinition::Error);
+    }
+
+    #[test]
+    fn constants_in_any_base() {
+        let (program, errors) = parse("int main(void) { return 0xFFu; }");
+
+        assert_matches!(errors[..], []);
+        assert_matches!(program.function, FunctionDefinition::Function {
+            body: Statement::Return(Expression::Constant(IntegerConstant { value: 255, ty: IntegerType::UnsignedInt })), ..
+        });
+
+        let text = "int main(void) { return 0779; }";
+        let (_, errors) = parse(text);
+        assert_matches!(&errors[..], [ParseError::InvalidNumber(_, IntParseError::InvalidDigit(span, '9', 8))] if span.range() == (27..28));
+        assert_eq!(errors[0].to_diagnostic(Span::new_with_unit(text.len(), 0, Id::Main)).message, "invalid digit '9' in octal constant");
+    }
+
+    #[test]
+    fn reports_end_of_file_once() {
+        let (_, errors) = parse("int main(void) { return");
+
+        assert_matches!(&errors[..], [ParseError::UnexpectedEOF(wanted, _)] if wanted == &[TokenKind::Constant]);
+    }
+
+    #[test]
+    fn missing_semicolon_has_fix_it() {
+        let text = "int main(void) { return 0\n}";
+        let (_, errors) = parse(text);
+        let diagnostic = errors[0].to_diagnostic(Span::new_with_unit(text.len(), 0, Id::Main));
+
+        assert_eq!(diagnostic.fixes.len(), 1);
+        assert_eq!(diagnostic.fixes[0].span.range(), 25..25);
+        assert_eq!(diagnostic.fixes[0].replacement, ";");
+    }
+
+    #[test]
+    fn trailing_tokens_report_the_first() {
+        let text = "int main(void) { return 0; } int x;";
+        let (_, errors) = parse(text);
+
+        assert_matches!(&errors[..], [ParseError::ExpectingEOF(token)] if token.kind == TokenKind::IntKeyword && token.span.range() == (29..32));
+        assert_eq!(errors[0].to_diagnostic(Span::new_with_unit(text.len(), 0, Id::Main)).message, "expected end of file but found 'int'");
+    }
+
+    #[test]
+    fn missing_semicolon_at_end_of_file_has_fix_it() {
+        let text = "int main(void) { return 0";
+        let (_, errors) = parse(text);
+        let diagnostic = errors[0].to_diagnostic(Span::new_with_unit(text.len(), 0, Id::Main));
+
+        assert_eq!(diagnostic.message, "expected ';' but found end of file");
+        assert_eq!(diagnostic.fixes.len(), 1);
+        assert_eq!(diagnostic.fixes[0].span.range(), 25..25);
+        assert_eq!(diagnostic.fixes[0].replacement, ";");
+    }
+
+    #[test]
+    fn messages_use_token_spelling() {
+        let text = "int main(void) { return 0 return; }";
+        let (_, errors) = parse(text);
+
+        assert_eq!(errors[0].to_diagnostic(Span::new_with_unit(text.len(), 0, Id::Main)).message, "expected ';' but found 'return'");
+        assert_eq!(ParseError::UnexpectedEOF(vec![TokenKind::Semicolon, TokenKind::Identifier, TokenKind::CloseBrace], None)
+            .to_diagnostic(Span::new_with_unit(0, 0, Id::Main)).message, "expected ';', identifier or '}' but found end of file");
     }
 }
\ No newline at end of file
@@ -0,0 +1,285 @@
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+use std::sync::Arc;
+use lexer::tokens::TokenKind;
+
+// A lossless concrete syntax tree in two layers. The green tree holds kinds, text and
+// widths only, so identical subtrees can be shared and the tree can be rebuilt cheaply.
+// The red tree wraps it on demand with absolute offsets and parent links.
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum NodeKind {
+    TranslationUnit,
+    FunctionDefinition,
+    // `int name(void)`
+    Declarator,
+    CompoundStatement,
+    ReturnStatement,
+    ConstantExpression,
+    // tokens skipped by error recovery, or a construct that failed to parse
+    Error,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct GreenToken {
+    pub kind: TokenKind,
+    pub text: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct GreenNode {
+    pub kind: NodeKind,
+    pub width: usize,
+    pub children: Vec<GreenElement>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum GreenElement {
+    Node(Arc<GreenNode>),
+    Token(Arc<GreenToken>),
+}
+
+impl GreenElement {
+    pub fn width(&self) -> usize {
+        match self {
+            GreenElement::Node(node) => node.width,
+            GreenElement::Token(token) => token.text.len(),
+        }
+    }
+}
+
+impl GreenNode {
+    pub fn new(kind: NodeKind, children: Vec<GreenElement>) -> GreenNode {
+        let width = children.iter().map(GreenElement::width).sum();
+        GreenNode { kind, width, children }
+    }
+}
+
+// Builds a green tree bottom up: tokens go into the innermost open node.
+#[derive(Default)]
+pub struct GreenBuilder {
+    // open nodes with the index in `children` where each one's children start
+    parents: Vec<(NodeKind, usize)>,
+    children: Vec<GreenElement>,
+}
+
+impl GreenBuilder {
+    pub fn new() -> GreenBuilder {
+        GreenBuilder::default()
+    }
+
+    pub fn start_node(&mut self, kind: NodeKind) {
+        self.parents.push((kind, self.children.len()));
+    }
+
+    pub fn token(&mut self, kind: TokenKind, text: &str) {
+        self.children.push(GreenElement::Token(Arc::new(GreenToken { kind, text: text.to_string() })));
+    }
+
+    pub fn finish_node(&mut self) {
+        let (kind, first) = self.parents.pop().expect("a node is open");
+        let children = self.children.split_off(first);
+        self.children.push(GreenElement::Node(Arc::new(GreenNode::new(kind, children))));
+    }
+
+    // The root: the one node left once every node has been finished.
+    pub fn finish(mut self) -> Arc<GreenNode> {
+        assert!(self.parents.is_empty(), "every node is finished");
+
+        match self.children.pop() {
+            Some(GreenElement::Node(root)) if self.children.is_empty() => root,
+            _ => panic!("the tree has a single root node"),
+        }
+    }
+}
+
+struct NodeData {
+    green: Arc<GreenNode>,
+    offset: usize,
+    parent: Option<SyntaxNode>,
+}
+
+#[derive(Clone)]
+pub struct SyntaxNode(Rc<NodeData>);
+
+#[derive(Clone)]
+pub struct SyntaxToken {
+    green: Arc<GreenToken>,
+    offset: usize,
+    parent: SyntaxNode,
+}
+
+#[derive(Clone)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl SyntaxNode {
+    pub fn new_root(green: Arc<GreenNode>) -> SyntaxNode {
+        SyntaxNode(Rc::new(NodeData { green, offset: 0, parent: None }))
+    }
+
+    pub fn kind(&self) -> NodeKind {
+        self.0.green.kind
+    }
+
+    pub fn green(&self) -> &Arc<GreenNode> {
+        &self.0.green
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.0.offset..self.0.offset + self.0.green.width
+    }
+
+    pub fn parent(&self) -> Option<&SyntaxNode> {
+        self.0.parent.as_ref()
+    }
+
+    pub fn children_with_tokens(&self) -> impl Iterator<Item = SyntaxElement> + '_ {
+        let mut offset = self.0.offset;
+
+        self.0.green.children.iter().map(move |child| {
+            let start = offset;
+            offset += child.width();
+
+            match child {
+                GreenElement::Node(green) =>
+                    SyntaxElement::Node(SyntaxNode(Rc::new(NodeData { green: green.clone(), offset: start, parent: Some(self.clone()) }))),
+                GreenElement::Token(green) =>
+                    SyntaxElement::Token(SyntaxToken { green: green.clone(), offset: start, parent: self.clone() }),
+            }
+        })
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = SyntaxNode> + '_ {
+        self.children_with_tokens().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+
+    // This node and every node under it, parents before their children.
+    pub fn descendants(&self) -> Vec<SyntaxNode> {
+        let mut nodes = vec![self.clone()];
+
+        for child in self.children() {
+            nodes.extend(child.descendants());
+        }
+
+        nodes
+    }
+
+    // Every token under this node, trivia included, in source order.
+    pub fn tokens(&self) -> Vec<SyntaxToken> {
+        let mut tokens = Vec::new();
+
+        for child in self.children_with_tokens() {
+            match child {
+                SyntaxElement::Node(node) => tokens.extend(node.tokens()),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+
+        tokens
+    }
+
+    // The token covering `offset`; between two tokens, the one that starts there.
+    pub fn token_at(&self, offset: usize) -> Option<SyntaxToken> {
+        self.tokens().into_iter().find(|token| token.range().contains(&offset))
+    }
+
+    fn dump(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        writeln!(f, "{:indent$}{:?}@{:?}", "", self.kind(), self.range(), indent = depth * 2)?;
+
+        for child in self.children_with_tokens() {
+            match child {
+                SyntaxElement::Node(node) => node.dump(f, depth + 1)?,
+                SyntaxElement::Token(token) =>
+                    writeln!(f, "{:indent$}{:?}@{:?} {:?}", "", token.kind(), token.range(), token.text(), indent = depth * 2 + 2)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// The source text the node was built from, byte for byte.
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for token in self.tokens() {
+            f.write_str(token.text())?;
+        }
+
+        Ok(())
+    }
+}
+
+// An indented outline of the tree with kinds, ranges and token text.
+impl fmt::Debug for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.dump(f, 0)
+    }
+}
+
+impl SyntaxToken {
+    pub fn kind(&self) -> TokenKind {
+        self.green.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.green.text
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.green.text.len()
+    }
+
+    pub fn parent(&self) -> &SyntaxNode {
+        &self.parent
+    }
+}
+
+impl fmt::Debug for SyntaxToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}@{:?} {:?}", self.kind(), self.range(), self.text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn red_tree_offsets() {
+        let mut builder = GreenBuilder::new();
+        builder.start_node(NodeKind::ReturnStatement);
+        builder.token(TokenKind::ReturnKeyword, "return");
+        builder.token(TokenKind::Whitespace, " ");
+        builder.start_node(NodeKind::ConstantExpression);
+        builder.token(TokenKind::Constant, "42");
+        builder.finish_node();
+        builder.token(TokenKind::Semicolon, ";");
+        builder.finish_node();
+
+        let root = SyntaxNode::new_root(builder.finish());
+        let constant = root.children().next().unwrap();
+
+        assert_eq!(root.to_string(), "return 42;");
+        assert_eq!(root.range(), 0..10);
+        assert_eq!(constant.kind(), NodeKind::ConstantExpression);
+        assert_eq!(constant.range(), 7..9);
+        assert_eq!(constant.parent().unwrap().kind(), NodeKind::ReturnStatement);
+        assert_eq!(root.token_at(9).unwrap().kind(), TokenKind::Semicolon);
+        assert_eq!(root.descendants().iter().map(SyntaxNode::kind).collect::<Vec<_>>(), vec![NodeKind::ReturnStatement, NodeKind::ConstantExpression]);
+        assert_eq!(format!("{:?}", root), "\
+ReturnStatement@0..10
+  ReturnKeyword@0..6 \"return\"
+  Whitespace@6..7 \" \"
+  ConstantExpression@7..9
+    Constant@7..9 \"42\"
+  Semicolon@9..10 \";\"
+");
+    }
+}
@@ -3,15 +3,19 @@ use crate::errors::ParseError;
 
 #[allow(dead_code)]
 pub struct TokenCollection {
+    // the tokens the parser sees, without trivia
     pub tokens: Vec<Token>,
     pub index: usize,
+    // every token including trivia, for building the syntax tree
+    pub all: Vec<Token>,
 }
 
 impl TokenCollection {
-    pub fn new(tokens: Vec<Token>) -> TokenCollection {
+    pub fn new(all: Vec<Token>) -> TokenCollection {
         TokenCollection {
-            tokens,
+            tokens: all.iter().filter(|token| !token.kind.is_trivia()).cloned().collect(),
             index: 0,
+            all,
         }
     }
 
@@ -25,22 +29,34 @@ impl TokenCollection {
         }
     }
 
+    pub fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index)
+    }
+
+    pub fn previous(&self) -> Option<&Token> {
+        self.index.checked_sub(1).and_then(|index| self.tokens.get(index))
+    }
+
     pub fn expect(&mut self, kind: TokenKind) -> Result<Token, ParseError> {
-        let actual = self.take_token();
+        self.expect_one_of(&[kind])
+    }
 
-        if let Some(actual_kind) = actual {
-            if actual_kind.kind == kind {
-                Ok(actual_kind.clone())
-            } else {
-                Err(ParseError::SyntaxError(actual_kind.clone(), kind))
+    // Only consumes the token when it matches, so a failed expectation leaves the
+    // offending token in place for error recovery to look at.
+    pub fn expect_one_of(&mut self, kinds: &[TokenKind]) -> Result<Token, ParseError> {
+        match self.peek() {
+            Some(actual) if kinds.contains(&actual.kind) => {
+                let token = actual.clone();
+                self.index += 1;
+                Ok(token)
             }
-        } else {
-            Err(ParseError::UnexpectedEOF(kind))
+            Some(actual) => Err(ParseError::SyntaxError(Box::new(actual.clone()), kinds.to_vec(), self.previous().map(|token| token.span.clone()))),
+            None => Err(ParseError::UnexpectedEOF(kinds.to_vec(), self.previous().map(|token| token.span.clone()))),
         }
     }
-    
+
     pub fn is_empty(&self) -> bool {
-        !(self.index < self.tokens.len()) 
+        self.index >= self.tokens.len()
     }
     
     pub fn last(&self) -> Option<&Token> {
@@ -50,13 +66,15 @@ impl TokenCollection {
 
 #[cfg(test)]
 mod tests {
+    use assert_matches::assert_matches;
+    use common::source_file::Id;
     use common::span::Span;
     use crate::token_collection::ParseError::{UnexpectedEOF};
     use super::*;
 
     #[test]
     fn test_tokens() {
-        let tokens = vec![Token::new(TokenKind::Constant, Span::new(0, 5))];
+        let tokens = vec![Token::new(TokenKind::Constant, Span::new_with_unit(0, 5, Id::Main))];
 
         let mut collection = TokenCollection::new(tokens);
 
@@ -64,6 +82,28 @@ mod tests {
         let result2 = collection.expect(TokenKind::Constant);
 
         assert!(result1.is_ok());
-        assert_eq!(result2, Err(UnexpectedEOF(TokenKind::Constant)));
+        assert_eq!(result2, Err(UnexpectedEOF(vec![TokenKind::Constant], Some(Span::new_with_unit(0, 5, Id::Main)))));
+    }
+
+    #[test]
+    fn failed_expect_does_not_consume() {
+        let tokens = vec![Token::new(TokenKind::Semicolon, Span::new_with_unit(0, 1, Id::Main))];
+
+        let mut collection = TokenCollection::new(tokens);
+
+        assert!(collection.expect(TokenKind::Constant).is_err());
+        assert!(collection.expect(TokenKind::Semicolon).is_ok());
+        assert!(collection.is_empty());
+    }
+
+    #[test]
+    fn expect_one_of_reports_every_kind() {
+        let tokens = vec![Token::new(TokenKind::CloseBrace, Span::new_with_unit(0, 1, Id::Main))];
+
+        let mut collection = TokenCollection::new(tokens);
+
+        assert_matches!(collection.expect_one_of(&[TokenKind::Semicolon, TokenKind::Constant]),
+            Err(ParseError::SyntaxError(_, kinds, None)) if kinds == [TokenKind::Semicolon, TokenKind::Constant]);
+        assert!(collection.expect_one_of(&[TokenKind::Semicolon, TokenKind::CloseBrace]).is_ok());
     }
 }
@@ -1,9 +1,146 @@
+use common::span::Span;
 use lexer::tokens::Token;
+use crate::ast::{IntegerConstant, IntegerType};
 use crate::errors::{ParseError, IntParseError};
 
-pub fn parse_number(text: &str, token: &Token) -> Result<i64, ParseError> {
-    match i64::from_str_radix(text, 10) {
-        Ok(num) => Ok(num),
-        Err(err) => Err(ParseError::InvalidNumber(token.clone(), IntParseError::from(err)))
+pub fn parse_number(text: &str, token: &Token) -> Result<IntegerConstant, ParseError> {
+    parse_integer(text, &token.span).map_err(|error| ParseError::InvalidNumber(Box::new(token.clone()), error))
+}
+
+// Parses a decimal, octal (`017`), hexadecimal (`0x1F`) or binary (`0b101`) integer constant,
+// with C23 digit separators and an optional `u`, `l` or `ll` suffix. `span` is the constant's,
+// errors point at the offending part of it.
+pub fn parse_integer(text: &str, span: &Span) -> Result<IntegerConstant, IntParseError> {
+    let bytes = text.as_bytes();
+
+    // a leading 0 is octal and also its own first digit
+    let (base, prefix) = match bytes {
+        [b'0', b'x' | b'X', ..] => (16, 2),
+        [b'0', b'b' | b'B', ..] => (2, 2),
+        [b'0', ..] => (8, 0),
+        _ => (10, 0),
+    };
+
+    let exponent: &[char] = if base == 16 { &['p', 'P'] } else { &['e', 'E'] };
+
+    if text.contains('.') || text[prefix..].contains(exponent) {
+        return Err(IntParseError::FloatingConstant(span.clone()));
+    }
+
+    // decimal digits are taken in every base so that `08` reports the 8
+    let digit_base = if base == 16 { 16 } else { 10 };
+    let is_digit = |c: u8| (c as char).is_digit(digit_base);
+    let mut value: Option<u64> = Some(0);
+    let mut digits = 0;
+    let mut position = prefix;
+
+    while let Some(&c) = bytes.get(position) {
+        if c == b'\'' {
+            if digits == 0 || !bytes.get(position + 1).copied().is_some_and(is_digit) {
+                return Err(IntParseError::MisplacedSeparator(span.sub_span(position..position + 1)));
+            }
+        } else if let Some(digit) = (c as char).to_digit(digit_base) {
+            if digit >= base {
+                return Err(IntParseError::InvalidDigit(span.sub_span(position..position + 1), c as char, base));
+            }
+
+            value = value.and_then(|value| value.checked_mul(base as u64)?.checked_add(digit as u64));
+            digits += 1;
+        } else {
+            break;
+        }
+
+        position += 1;
+    }
+
+    if digits == 0 {
+        return Err(IntParseError::MissingDigits(span.sub_span(0..position)));
+    }
+
+    let suffix = &text[position..];
+    let (unsigned, longs) = match suffix.strip_prefix(['u', 'U']).or_else(|| suffix.strip_suffix(['u', 'U'])) {
+        Some(longs) => (true, longs),
+        None => (false, suffix),
+    };
+
+    let longs = match longs {
+        "" => 0,
+        "l" | "L" => 1,
+        "ll" | "LL" => 2,
+        _ => return Err(IntParseError::InvalidSuffix(span.sub_span(position..text.len()), suffix.to_string())),
+    };
+
+    // decimal constants without `u` never become unsigned, the others take the first type that fits
+    use IntegerType::*;
+    let candidates: &[IntegerType] = match (unsigned, longs, base == 10) {
+        (false, 0, true) => &[Int, Long, LongLong],
+        (false, 0, false) => &[Int, UnsignedInt, Long, UnsignedLong, LongLong, UnsignedLongLong],
+        (false, 1, true) => &[Long, LongLong],
+        (false, 1, false) => &[Long, UnsignedLong, LongLong, UnsignedLongLong],
+        (false, _, true) => &[LongLong],
+        (false, _, false) => &[LongLong, UnsignedLongLong],
+        (true, 0, _) => &[UnsignedInt, UnsignedLong, UnsignedLongLong],
+        (true, 1, _) => &[UnsignedLong, UnsignedLongLong],
+        (true, _, _) => &[UnsignedLongLong],
+    };
+
+    let largest = candidates[candidates.len() - 1];
+
+    match value.and_then(|value| candidates.iter().find(|ty| value <= ty.max()).map(|&ty| (value, ty))) {
+        Some((value, ty)) => Ok(IntegerConstant { value, ty }),
+        None => Err(IntParseError::TooLarge(span.clone(), largest)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use common::source_file::Id;
+    use super::*;
+
+    fn parse(text: &str) -> Result<IntegerConstant, IntParseError> {
+        parse_integer(text, &Span::new_with_unit(10, text.len(), Id::Main))
+    }
+
+    fn constant(value: u64, ty: IntegerType) -> Result<IntegerConstant, IntParseError> {
+        Ok(IntegerConstant { value, ty })
+    }
+
+    #[test]
+    fn bases_and_separators() {
+        assert_eq!(parse("0"), constant(0, IntegerType::Int));
+        assert_eq!(parse("42"), constant(42, IntegerType::Int));
+        assert_eq!(parse("0x1F"), constant(31, IntegerType::Int));
+        assert_eq!(parse("017"), constant(15, IntegerType::Int));
+        assert_eq!(parse("0b101"), constant(5, IntegerType::Int));
+        assert_eq!(parse("1'000'000"), constant(1_000_000, IntegerType::Int));
+        assert_eq!(parse("0xFF'FF"), constant(0xffff, IntegerType::Int));
+    }
+
+    #[test]
+    fn types_follow_value_and_suffix() {
+        assert_eq!(parse("123ULL"), constant(123, IntegerType::UnsignedLongLong));
+        assert_eq!(parse("1lu"), constant(1, IntegerType::UnsignedLong));
+        assert_eq!(parse("1u"), constant(1, IntegerType::UnsignedInt));
+        assert_eq!(parse("1L"), constant(1, IntegerType::Long));
+        assert_eq!(parse("2147483648"), constant(2147483648, IntegerType::Long));
+        assert_eq!(parse("0x80000000"), constant(0x80000000, IntegerType::UnsignedInt));
+        assert_eq!(parse("4294967296u"), constant(4294967296, IntegerType::UnsignedLong));
+        assert_eq!(parse("0xFFFFFFFFFFFFFFFF"), constant(u64::MAX, IntegerType::UnsignedLong));
+        assert_eq!(parse("9223372036854775807ll"), constant(i64::MAX as u64, IntegerType::LongLong));
+    }
+
+    #[test]
+    fn errors_point_at_the_bad_part() {
+        assert_matches!(parse("0128"), Err(IntParseError::InvalidDigit(span, '8', 8)) if span.range() == (13..14));
+        assert_matches!(parse("0b1012"), Err(IntParseError::InvalidDigit(span, '2', 2)) if span.range() == (15..16));
+        assert_matches!(parse("12lL"), Err(IntParseError::InvalidSuffix(span, suffix)) if span.range() == (12..14) && suffix == "lL");
+        assert_matches!(parse("12uu"), Err(IntParseError::InvalidSuffix(_, _)));
+        assert_matches!(parse("0x"), Err(IntParseError::MissingDigits(span)) if span.range() == (10..12));
+        assert_matches!(parse("1''0"), Err(IntParseError::MisplacedSeparator(span)) if span.range() == (11..12));
+        assert_matches!(parse("1.5"), Err(IntParseError::FloatingConstant(_)));
+        assert_matches!(parse("1e5"), Err(IntParseError::FloatingConstant(_)));
+        assert_matches!(parse("9223372036854775808"), Err(IntParseError::TooLarge(_, IntegerType::LongLong)));
+        assert_matches!(parse("0x10000000000000000"), Err(IntParseError::TooLarge(_, IntegerType::UnsignedLongLong)));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,76 @@
+use common::diagnostics::Diagnostic;
+use common::error_codes::ErrorCode;
+use common::span::Span;
+use common::warnings::Warning;
+
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub enum PreprocessorError {
+    IncludeNotFound(Span, String), // header name, as written
+    IncludeTooDeep(Span),
+    ErrorDirective(Span, String), // message
+    UnknownDirective(Span),
+    InvalidDirective(Span, String), // what is wrong with it
+    UnterminatedConditional(Span), // the opening #if
+    UnmatchedConditional(Span),
+    WrongArgumentCount(Span, String, usize, usize), // invocation, macro, expected, found
+    UnterminatedInvocation(Span, String), // macro name, macro
+    InvalidCondition(Span, String),
+    InvalidPaste(Span, String), // the `##`, pasted text
+    MacroRedefined(Span, String, Span), // new definition, macro, previous definition
+}
+
+impl PreprocessorError {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        match self {
+            PreprocessorError::IncludeNotFound(span, name) =>
+                Diagnostic::error(span.clone(), format!("cannot find header {}", name))
+                    .with_code(ErrorCode::IncludeNotFound)
+                    .with_label(span.clone(), "included here"),
+            PreprocessorError::IncludeTooDeep(span) =>
+                Diagnostic::error(span.clone(), "#include nested too deeply")
+                    .with_code(ErrorCode::IncludeTooDeep)
+                    .with_label(span.clone(), "included here"),
+            PreprocessorError::ErrorDirective(span, message) =>
+                Diagnostic::error(span.clone(), format!("#error {}", message))
+                    .with_code(ErrorCode::ErrorDirective)
+                    .with_label(span.clone(), "error directive here"),
+            PreprocessorError::UnknownDirective(span) =>
+                Diagnostic::error(span.clone(), "unknown preprocessing directive")
+                    .with_code(ErrorCode::InvalidDirective)
+                    .with_label(span.clone(), "not a directive"),
+            PreprocessorError::InvalidDirective(span, message) =>
+                Diagnostic::error(span.clone(), message.clone())
+                    .with_code(ErrorCode::InvalidDirective)
+                    .with_label(span.clone(), "in this directive"),
+            PreprocessorError::UnterminatedConditional(span) =>
+                Diagnostic::error(span.clone(), "unterminated conditional directive")
+                    .with_code(ErrorCode::UnterminatedConditional)
+                    .with_label(span.clone(), "opened here")
+                    .with_help("add a matching #endif"),
+            PreprocessorError::UnmatchedConditional(span) =>
+                Diagnostic::error(span.clone(), "conditional directive without a matching #if")
+                    .with_code(ErrorCode::UnmatchedConditional)
+                    .with_label(span.clone(), "no open #if for this"),
+            PreprocessorError::WrongArgumentCount(span, name, expected, found) =>
+                Diagnostic::error(span.clone(), format!("macro '{}' expects {} argument(s) but {} were given", name, expected, found))
+                    .with_code(ErrorCode::MacroArguments)
+                    .with_label(span.clone(), "invoked here"),
+            PreprocessorError::UnterminatedInvocation(span, name) =>
+                Diagnostic::error(span.clone(), format!("unterminated argument list invoking macro '{}'", name))
+                    .with_code(ErrorCode::MacroArguments)
+                    .with_label(span.clone(), "invoked here"),
+            PreprocessorError::InvalidCondition(span, message) =>
+                Diagnostic::error(span.clone(), format!("invalid #if condition: {}", message))
+                    .with_code(ErrorCode::InvalidCondition)
+                    .with_label(span.clone(), "in this condition"),
+            PreprocessorError::InvalidPaste(span, text) =>
+                Diagnostic::error(span.clone(), format!("pasting gives '{}', which is not a valid preprocessing token", text))
+                    .with_code(ErrorCode::InvalidPaste)
+                    .with_label(span.clone(), "pasted here"),
+            PreprocessorError::MacroRedefined(span, name, previous) =>
+                Diagnostic::warning(Warning::MacroRedefined, span.clone(), format!("'{}' macro redefined", name))
+                    .with_label(span.clone(), "redefined here")
+                    .with_label(previous.clone(), "previous definition is here"),
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use common::span::Span;
+use crate::errors::PreprocessorError;
+use crate::tokens::{PpKind, PpToken};
+
+// #if arithmetic is done in intmax_t or uintmax_t.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Value {
+    pub bits: u64,
+    pub unsigned: bool,
+}
+
+impl Value {
+    pub fn signed(value: i64) -> Value {
+        Value { bits: value as u64, unsigned: false }
+    }
+
+    fn boolean(value: bool) -> Value {
+        Value::signed(value as i64)
+    }
+
+    pub fn is_true(&self) -> bool {
+        self.bits != 0
+    }
+
+    fn as_i64(&self) -> i64 {
+        self.bits as i64
+    }
+}
+
+type Result<T> = std::result::Result<T, PreprocessorError>;
+
+// Evaluates the condition of an #if, after `defined` and macros have been replaced.
+// `line` is where errors at the end of the condition are reported.
+pub fn evaluate(tokens: &[PpToken], line: &Span) -> Result<Value> {
+    let mut evaluator = Evaluator { tokens, position: 0, line };
+
+    if tokens.is_empty() {
+        return Err(evaluator.error_at_end("#if with no expression"));
+    }
+
+    let value = evaluator.conditional(true)?;
+
+    match evaluator.peek() {
+        Some(token) => Err(PreprocessorError::InvalidCondition(token.span.clone(), format!("unexpected '{}'", token.text))),
+        None => Ok(value),
+    }
+}
+
+struct Evaluator<'a> {
+    tokens: &'a [PpToken],
+    position: usize,
+    line: &'a Span,
+}
+
+// binding power of each binary operator, higher binds tighter
+fn precedence(operator: &str) -> Option<u8> {
+    Some(match operator {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" => 6,
+        "<" | ">" | "<=" | ">=" => 7,
+        "<<" | ">>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        _ => return None,
+    })
+}
+
+impl<'a> Evaluator<'a> {
+    fn peek(&self) -> Option<&'a PpToken> {
+        self.tokens.get(self.position)
+    }
+
+    fn error_at_end(&self, message: &str) -> PreprocessorError {
+        PreprocessorError::InvalidCondition(self.line.clone(), message.to_string())
+    }
+
+    fn expect(&mut self, punctuator: &str) -> Result<()> {
+        match self.peek() {
+            Some(token) if token.is(punctuator) => {
+                self.position += 1;
+                Ok(())
+            }
+            Some(token) => Err(PreprocessorError::InvalidCondition(token.span.clone(), format!("expected '{}' but found '{}'", punctuator, token.text))),
+            None => Err(self.error_at_end(&format!("expected '{}'", punctuator))),
+        }
+    }
+
+    // `live` is false inside the operand that short-circuiting skips, where
+    // division by zero is not an error.
+    fn conditional(&mut self, live: bool) -> Result<Value> {
+        let condition = self.binary(1, live)?;
+
+        if !self.peek().is_some_and(|token| token.is("?")) {
+            return Ok(condition);
+        }
+
+        self.position += 1;
+        let when_true = self.conditional(live && condition.is_true())?;
+        self.expect(":")?;
+        let when_false = self.conditional(live && !condition.is_true())?;
+
+        let unsigned = when_true.unsigned || when_false.unsigned;
+        let result = if condition.is_true() { when_true } else { when_false };
+
+        Ok(Value { unsigned, ..result })
+    }
+
+    fn binary(&mut self, min_precedence: u8, live: bool) -> Result<Value> {
+        let mut left = self.unary(live)?;
+
+        while let Some(token) = self.peek()
+            && token.kind == PpKind::Punctuator
+            && let Some(precedence) = precedence(&token.text)
+            && precedence >= min_precedence {
+            self.position += 1;
+
+            let right_live = match token.text.as_str() {
+                "&&" => live && left.is_true(),
+                "||" => live && !left.is_true(),
+                _ => live,
+            };
+
+            let right = self.binary(precedence + 1, right_live)?;
+            left = apply(token, left, right, right_live)?;
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self, live: bool) -> Result<Value> {
+        let Some(token) = self.peek() else {
+            return Err(self.error_at_end("expected a value"));
+        };
+
+        if token.kind == PpKind::Punctuator && matches!(token.text.as_str(), "+" | "-" | "~" | "!") {
+            self.position += 1;
+            let operand = self.unary(live)?;
+
+            return Ok(match token.text.as_str() {
+                "+" => operand,
+                "-" => Value { bits: operand.bits.wrapping_neg(), ..operand },
+                "~" => Value { bits: !operand.bits, ..operand },
+                _ => Value::boolean(!operand.is_true()),
+            });
+        }
+
+        self.primary(live)
+    }
+
+    fn primary(&mut self, live: bool) -> Result<Value> {
+        let token = self.peek().ok_or_else(|| self.error_at_end("expected a value"))?;
+        self.position += 1;
+
+        match token.kind {
+            PpKind::Number => parse_number(token),
+            PpKind::CharConstant => parse_char(token),
+            // identifiers left after macro expansion are 0, except C23's true
+            PpKind::Identifier => Ok(Value::boolean(token.text == "true")),
+            PpKind::Punctuator if token.is("(") => {
+                let value = self.conditional(live)?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            _ => Err(PreprocessorError::InvalidCondition(token.span.clone(), format!("'{}' is not allowed in a condition", token.text))),
+        }
+    }
+}
+
+fn apply(operator: &PpToken, left: Value, right: Value, live: bool) -> Result<Value> {
+    let unsigned = left.unsigned || right.unsigned;
+    let arithmetic = |bits: u64| Ok(Value { bits, unsigned });
+
+    match operator.text.as_str() {
+        "||" => Ok(Value::boolean(left.is_true() || right.is_true())),
+        "&&" => Ok(Value::boolean(left.is_true() && right.is_true())),
+        "|" => arithmetic(left.bits | right.bits),
+        "^" => arithmetic(left.bits ^ right.bits),
+        "&" => arithmetic(left.bits & right.bits),
+        "==" => Ok(Value::boolean(left.bits == right.bits)),
+        "!=" => Ok(Value::boolean(left.bits != right.bits)),
+        "<" | ">" | "<=" | ">=" => {
+            let ordering = if unsigned { left.bits.cmp(&right.bits) } else { left.as_i64().cmp(&right.as_i64()) };
+
+            Ok(Value::boolean(match operator.text.as_str() {
+                "<" => ordering.is_lt(),
+                ">" => ordering.is_gt(),
+                "<=" => ordering.is_le(),
+                _ => ordering.is_ge(),
+            }))
+        }
+        "<<" | ">>" => {
+            let amount = (right.bits & 63) as u32;
+            let bits = match (operator.text.as_str(), left.unsigned) {
+                ("<<", _) => left.bits.wrapping_shl(amount),
+                (_, true) => left.bits >> amount,
+                (_, false) => (left.as_i64() >> amount) as u64,
+            };
+
+            Ok(Value { bits, unsigned: left.unsigned })
+        }
+        "+" => arithmetic(left.bits.wrapping_add(right.bits)),
+        "-" => arithmetic(left.bits.wrapping_sub(right.bits)),
+        "*" => arithmetic(left.bits.wrapping_mul(right.bits)),
+        "/" | "%" => {
+            if right.bits == 0 {
+                return if live {
+                    Err(PreprocessorError::InvalidCondition(operator.span.clone(), "division by zero".to_string()))
+                } else {
+                    arithmetic(0)
+                };
+            }
+
+            let bits = match (operator.text.as_str(), unsigned) {
+                ("/", true) => left.bits / right.bits,
+                ("/", false) => left.as_i64().wrapping_div(right.as_i64()) as u64,
+                (_, true) => left.bits % right.bits,
+                (_, false) => left.as_i64().wrapping_rem(right.as_i64()) as u64,
+            };
+
+            arithmetic(bits)
+        }
+        _ => unreachable!("not a binary operator"),
+    }
+}
+
+fn parse_number(token: &PpToken) -> Result<Value> {
+    let error = |message: &str| Err(PreprocessorError::InvalidCondition(token.span.clone(), message.to_string()));
+    let text = token.text.replace('\'', "");
+    let digits_end = text.find(['u', 'U', 'l', 'L']).unwrap_or(text.len());
+    let (digits, suffix) = text.split_at(digits_end);
+
+    if !matches!(suffix.to_ascii_lowercase().as_str(), "" | "u" | "l" | "ul" | "lu" | "ll" | "ull" | "llu") {
+        return error(&format!("invalid suffix '{}' on integer constant", suffix));
+    }
+
+    let (radix, digits) = if let Some(hex) = digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        (16, hex)
+    } else if let Some(binary) = digits.strip_prefix("0b").or(digits.strip_prefix("0B")) {
+        (2, binary)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        (8, &digits[1..])
+    } else {
+        (10, digits)
+    };
+
+    if radix == 10 && digits.contains(['.', 'e', 'E']) || radix == 16 && digits.contains(['.', 'p', 'P']) {
+        return error("floating constant in preprocessor expression");
+    }
+
+    match u64::from_str_radix(digits, radix) {
+        Ok(bits) => Ok(Value { bits, unsigned: suffix.contains(['u', 'U']) || bits > i64::MAX as u64 }),
+        Err(err) if *err.kind() == std::num::IntErrorKind::PosOverflow => error("integer constant is too large"),
+        Err(_) => error(&format!("invalid integer constant '{}'", token.text)),
+    }
+}
+
+fn parse_char(token: &PpToken) -> Result<Value> {
+    let error = || Err(PreprocessorError::InvalidCondition(token.span.clone(), format!("invalid character constant {}", token.text)));
+    let quote = token.text.find('\'').unwrap_or(0);
+    let plain = quote == 0;
+    let body = &token.text[quote + 1..token.text.len() - 1];
+    let mut chars = body.chars().peekable();
+    let mut value: u64 = 0;
+    let mut count = 0;
+
+    while let Some(c) = chars.next() {
+        let code = if c != '\\' {
+            c as u64
+        } else {
+            match chars.next() {
+                Some('n') => 10,
+                Some('t') => 9,
+                Some('r') => 13,
+                Some('a') => 7,
+                Some('b') => 8,
+                Some('f') => 12,
+                Some('v') => 11,
+                Some('e') => 27,
+                Some(c @ ('\\' | '\'' | '"' | '?')) => c as u64,
+                Some('x') => {
+                    let mut code = 0u64;
+                    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(16)) {
+                        code = code.wrapping_mul(16) + digit as u64;
+                        chars.next();
+                    }
+                    code
+                }
+                Some(c @ '0'..='7') => {
+                    let mut code = c.to_digit(8).unwrap() as u64;
+                    for _ in 0..2 {
+                        match chars.peek().and_then(|c| c.to_digit(8)) {
+                            Some(digit) => {
+                                code = code * 8 + digit as u64;
+                                chars.next();
+                            }
+                            None => break,
+                        }
+                    }
+                    code
+                }
+                _ => return error(),
+            }
+        };
+
+        value = (value << 8) | (code & 0xff);
+        count += 1;
+    }
+
+    match count {
+        0 => error(),
+        // a plain char is signed
+        1 if plain => Ok(Value::signed(value as u8 as i8 as i64)),
+        _ => Ok(Value::signed(value as i32 as i64)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use common::source_file::Id;
+    use crate::tokens::tokenize;
+    use super::*;
+
+    fn eval(text: &str) -> Result<Value> {
+        let mut tokens = tokenize(text, &Id::Main);
+        tokens.pop();
+
+        evaluate(&tokens, &Span::new_with_unit(0, text.len(), Id::Main))
+    }
+
+    #[test]
+    fn arithmetic_and_precedence() {
+        assert_eq!(eval("1 + 2 * 3 == 7").unwrap(), Value::signed(1));
+        assert_eq!(eval("(1 << 4) | 0x0F").unwrap(), Value::signed(31));
+        assert_eq!(eval("-7 / 2").unwrap(), Value::signed(-3));
+        assert_eq!(eval("1 ? 2 : 3").unwrap(), Value::signed(2));
+    }
+
+    #[test]
+    fn unsigned_conversions() {
+        assert!(!eval("-1 < 0u").unwrap().is_true());
+        assert!(eval("-1 < 0").unwrap().is_true());
+        assert!(eval("0xFFFFFFFFFFFFFFFF > 0").unwrap().is_true());
+    }
+
+    #[test]
+    fn short_circuit_skips_division_by_zero() {
+        assert_eq!(eval("0 && 1 / 0").unwrap(), Value::signed(0));
+        assert_matches!(eval("1 && 1 / 0"), Err(PreprocessorError::InvalidCondition(_, message)) if message == "division by zero");
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!(eval("'A'").unwrap(), Value::signed(65));
+        assert_eq!(eval("'\\377'").unwrap(), Value::signed(-1));
+        assert_eq!(eval("0b101 + 017 + 1'000L").unwrap(), Value::signed(1020));
+        assert_eq!(eval("undefined_name").unwrap(), Value::signed(0));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_matches!(eval("1 +"), Err(PreprocessorError::InvalidCondition(_, message)) if message == "expected a value");
+        assert_matches!(eval("(1"), Err(PreprocessorError::InvalidCondition(_, message)) if message == "expected ')'");
+        assert_matches!(eval("1.5"), Err(PreprocessorError::InvalidCondition(..)));
+    }
+}
@@ -0,0 +1,6 @@
+pub mod tokens;
+pub mod errors;
+pub mod span_map;
+pub mod splice;
+pub mod expression;
+pub mod preprocessor;
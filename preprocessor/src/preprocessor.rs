@@ -0,0 +1,1186 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use ariadne::Span as ASpan;
+use common::source_file::{Id, SourceFile};
+use common::span::{Expansion, Span};
+use crate::errors::PreprocessorError;
+use crate::expression::evaluate;
+use crate::span_map::SpanMap;
+use crate::splice::{splice, Spliced};
+use crate::tokens::{tokenize, tokenize_with, HideSet, PpKind, PpToken};
+
+// Deep enough for any real header tree, shallow enough to stop a header including itself.
+const MAX_INCLUDE_DEPTH: usize = 200;
+
+#[derive(Debug, Default, Clone)]
+pub struct PreprocessorOptions {
+    // searched in order for `#include <...>`, and after the including file's directory for `#include "..."`
+    pub include_paths: Vec<PathBuf>,
+    // `-D` definitions as name and replacement text
+    pub defines: Vec<(String, String)>,
+    // must match the lexer's setting so both agree on where comments end
+    pub nested_comments: bool,
+    // replace `??=` and the other trigraphs before anything else
+    pub trigraphs: bool,
+}
+
+pub struct Preprocessed {
+    pub text: String,
+    // maps spans in `text` back to the files and macro uses they came from
+    pub map: SpanMap,
+    // headers and built-in definitions that spans may now point into
+    pub included: Vec<SourceFile>,
+    // the `#include` directive each header was first included by
+    pub included_at: HashMap<Id, Span>,
+    pub errors: Vec<PreprocessorError>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum MacroKind {
+    Object,
+    // a variadic macro has `__VA_ARGS__` as its last parameter
+    Function { params: Vec<String>, variadic: bool },
+    File,
+    Line,
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    kind: MacroKind,
+    body: Vec<PpToken>,
+    // the macro's name in its definition
+    span: Span,
+}
+
+impl Macro {
+    fn same_definition(&self, other: &Macro) -> bool {
+        self.kind == other.kind
+            && self.body.len() == other.body.len()
+            && self.body.iter().zip(&other.body).enumerate().all(|(i, (a, b))|
+                a.text == b.text && (i == 0 || a.has_space == b.has_space))
+    }
+
+    fn param_index(&self, token: &PpToken) -> Option<usize> {
+        match &self.kind {
+            MacroKind::Function { params, .. } if token.kind == PpKind::Identifier =>
+                params.iter().position(|param| *param == token.text),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Conditional {
+    // the opening directive
+    span: Span,
+    parent_active: bool,
+    // whether some group of this conditional has been taken already
+    taken: bool,
+    active: bool,
+    seen_else: bool,
+}
+
+struct FileState {
+    unit: Id,
+    path: PathBuf,
+    // name and line offset set by #line
+    presumed: String,
+    line_delta: i64,
+    newlines: Vec<usize>,
+    // the text the tokens were read from; token spans are in the original text
+    spliced: Spliced,
+    conditionals: Vec<Conditional>,
+    // how far the file's text has been written to the output
+    copied: usize,
+}
+
+impl FileState {
+    fn line(&self, offset: usize) -> i64 {
+        self.newlines.partition_point(|&newline| newline < offset) as i64 + 1
+    }
+}
+
+struct Preprocessor<'a> {
+    options: &'a PreprocessorOptions,
+    macros: HashMap<String, Macro>,
+    files: Vec<FileState>,
+    output: String,
+    map: SpanMap,
+    included: Vec<SourceFile>,
+    included_at: HashMap<Id, Span>,
+    errors: Vec<PreprocessorError>,
+}
+
+pub fn preprocess(file: &SourceFile, options: &PreprocessorOptions) -> Preprocessed {
+    let output = Id::Unit(format!("{} (preprocessed)", file.filename));
+    let mut preprocessor = Preprocessor {
+        options,
+        macros: HashMap::new(),
+        files: Vec::new(),
+        output: String::new(),
+        map: SpanMap::new(output, Span::new_with_unit(0, 0, file.id.clone())),
+        included: Vec::new(),
+        included_at: HashMap::new(),
+        errors: Vec::new(),
+    };
+
+    preprocessor.predefine("<built-in>", builtin_definitions());
+
+    for (name, kind) in [("__FILE__", MacroKind::File), ("__LINE__", MacroKind::Line)] {
+        let span = Span::new_with_unit(0, 0, Id::Unit("<built-in>".to_string()));
+        preprocessor.macros.insert(name.to_string(), Macro { kind, body: Vec::new(), span });
+    }
+
+    let command_line: String = options.defines.iter()
+        .map(|(name, value)| format!("#define {} {}\n", name, value))
+        .collect();
+
+    if !command_line.is_empty() {
+        preprocessor.predefine("<command line>", command_line);
+    }
+
+    let text = file.text().to_string();
+    preprocessor.run_file(file.id.clone(), PathBuf::from(&file.filename), &text);
+
+    Preprocessed {
+        text: preprocessor.output,
+        map: preprocessor.map,
+        included: preprocessor.included,
+        included_at: preprocessor.included_at,
+        errors: preprocessor.errors,
+    }
+}
+
+fn builtin_definitions() -> String {
+    // SOURCE_DATE_EPOCH keeps __DATE__ and __TIME__ reproducible
+    let seconds = std::env::var("SOURCE_DATE_EPOCH").ok()
+        .and_then(|epoch| epoch.parse::<i64>().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64));
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let time = seconds.rem_euclid(86400);
+    let months = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+    format!(
+        "#define __STDC__ 1\n\
+         #define __STDC_VERSION__ 201710L\n\
+         #define __STDC_HOSTED__ 1\n\
+         #define __niamc__ 1\n\
+         #define __DATE__ \"{} {:>2} {}\"\n\
+         #define __TIME__ \"{:02}:{:02}:{:02}\"\n",
+        months[month as usize - 1], day, year, time / 3600, time / 60 % 60, time % 60)
+}
+
+// Days since 1970-01-01 to a (year, month, day) date in the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+
+    (year_of_era + era * 400 + (month <= 2) as i64, month, day)
+}
+
+fn span_between(first: &Span, last: &Span) -> Span {
+    Span::new_with_unit(first.start(), last.end().max(first.start()) - first.start(), first.source().clone())
+}
+
+fn with_name(hideset: &HideSet, name: &str) -> HideSet {
+    let mut names = (**hideset).clone();
+    names.insert(name.to_string());
+    Rc::new(names)
+}
+
+fn union(first: &HideSet, second: &HideSet) -> HideSet {
+    Rc::new(first.union(second).cloned().collect::<BTreeSet<_>>())
+}
+
+fn intersection(first: &HideSet, second: &HideSet) -> HideSet {
+    Rc::new(first.intersection(second).cloned().collect::<BTreeSet<_>>())
+}
+
+fn string_literal(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// Whether two tokens written next to each other would be read back as something else.
+fn would_merge(previous: Option<char>, next: &str) -> bool {
+    let (Some(previous), Some(next)) = (previous, next.chars().next()) else {
+        return false;
+    };
+    let word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let joins = |c: char| c.is_ascii_punctuation() && !"()[]{};,".contains(c);
+
+    (word(previous) || previous == '.') && (word(next) || next == '.' || next == '\'' || next == '"')
+        || joins(previous) && joins(next)
+}
+
+impl<'a> Preprocessor<'a> {
+    fn active(&self) -> bool {
+        self.files.last()
+            .and_then(|file| file.conditionals.last())
+            .is_none_or(|conditional| conditional.active)
+    }
+
+    fn predefine(&mut self, name: &str, text: String) {
+        let unit = Id::Unit(name.to_string());
+        let mut file = SourceFile::from_string(unit.clone(), &text);
+        file.filename = name.to_string();
+        self.included.push(file);
+
+        let tokens = tokenize(&text, &unit);
+
+        for line in tokens.split(|token| matches!(token.kind, PpKind::Newline | PpKind::Eof)) {
+            if let [hash, rest @ ..] = line && hash.is_hash() {
+                self.directive(hash, rest);
+            }
+        }
+    }
+
+    fn run_file(&mut self, unit: Id, path: PathBuf, text: &str) {
+        let spliced = splice(text, self.options.trigraphs);
+        let mut tokens = tokenize_with(&spliced.text, &unit, self.options.nested_comments);
+
+        for token in &mut tokens {
+            let range = spliced.original_range(token.span.range());
+            token.span = Span::new_with_unit(range.start, range.end - range.start, unit.clone());
+        }
+
+        self.files.push(FileState {
+            unit: unit.clone(),
+            presumed: path.display().to_string(),
+            path,
+            line_delta: 0,
+            newlines: SourceFile::offsets(text),
+            spliced,
+            conditionals: Vec::new(),
+            copied: 0,
+        });
+
+        let mut pending = Vec::new();
+        let mut index = 0;
+
+        while index < tokens.len() {
+            let token = &tokens[index];
+
+            if token.kind == PpKind::Eof {
+                let pending = std::mem::take(&mut pending);
+                self.write(pending);
+
+                if self.active() {
+                    self.copy(text.len());
+                } else {
+                    self.skip(text.len());
+                }
+
+                break;
+            }
+
+            if token.at_line_start && token.is_hash() {
+                let pending = std::mem::take(&mut pending);
+                self.write(pending);
+
+                let end = tokens[index..].iter()
+                    .position(|token| matches!(token.kind, PpKind::Newline | PpKind::Eof))
+                    .map_or(tokens.len() - 1, |end| index + end);
+
+                self.skip(token.span.start());
+                self.directive(token, &tokens[index + 1..end]);
+                self.skip(tokens[end].span.end());
+                index = end + 1;
+                continue;
+            }
+
+            if self.active() {
+                pending.push(token.clone());
+            }
+
+            index += 1;
+        }
+
+        let file = self.files.pop().expect("a file is being processed");
+
+        for conditional in file.conditionals {
+            self.errors.push(PreprocessorError::UnterminatedConditional(conditional.span));
+        }
+    }
+
+    // Writes the file's text up to `end` as spliced.
+    fn copy(&mut self, end: usize) {
+        let file = self.files.last_mut().expect("a file is being processed");
+
+        if end <= file.copied {
+            return;
+        }
+
+        let range = file.spliced.spliced_offset(file.copied)..file.spliced.spliced_offset(end);
+
+        for (spliced, original, exact) in file.spliced.origins(range.clone()) {
+            let output_start = self.output.len() + spliced.start - range.start;
+            let origin = Span::new_with_unit(original.start, original.end - original.start, file.unit.clone());
+
+            if exact {
+                self.map.push_exact(output_start, origin);
+            } else {
+                self.map.push_expanded(output_start, spliced.end - spliced.start, origin);
+            }
+        }
+
+        self.output.push_str(&file.spliced.text[range]);
+        file.copied = end;
+    }
+
+    // Drops the file's text up to `end`, keeping only its line breaks.
+    fn skip(&mut self, end: usize) {
+        let file = self.files.last_mut().expect("a file is being processed");
+
+        if end <= file.copied {
+            return;
+        }
+
+        for _ in file.line(file.copied)..file.line(end) {
+            self.output.push('\n');
+        }
+
+        file.copied = end;
+    }
+
+    // Expands the tokens of a run of text lines and writes the result.
+    fn write(&mut self, tokens: Vec<PpToken>) {
+        if tokens.is_empty() {
+            return;
+        }
+
+        for token in self.expand(tokens) {
+            let Some(site) = &token.expansion else {
+                self.copy(token.span.end());
+                continue;
+            };
+
+            let copied = self.files.last().expect("a file is being processed").copied;
+            let first = site.start() >= copied;
+
+            if first {
+                self.copy(site.start());
+            }
+
+            // an invocation started inside an expansion can reach past the end of that expansion
+            let file = self.files.last_mut().expect("a file is being processed");
+            file.copied = file.copied.max(site.end());
+
+            if (!first && token.has_space) || would_merge(self.output.chars().last(), &token.text) {
+                self.output.push(' ');
+            }
+
+            self.map.push_expanded(self.output.len(), token.text.len(), site.clone());
+            self.output.push_str(&token.text);
+        }
+    }
+
+    fn expand(&mut self, tokens: Vec<PpToken>) -> Vec<PpToken> {
+        let mut input: VecDeque<PpToken> = tokens.into();
+        let mut output = Vec::new();
+
+        while let Some(token) = input.pop_front() {
+            if token.kind != PpKind::Identifier || token.hideset.contains(&token.text) {
+                output.push(token);
+                continue;
+            }
+
+            let Some(definition) = self.macros.get(&token.text).cloned() else {
+                output.push(token);
+                continue;
+            };
+
+            let site = token.expansion.clone().unwrap_or_else(|| token.span.clone());
+
+            match &definition.kind {
+                MacroKind::File | MacroKind::Line => {
+                    let (kind, text) = match definition.kind {
+                        MacroKind::File => (PpKind::StringLiteral, string_literal(&self.presumed_name())),
+                        _ => (PpKind::Number, self.presumed_line(&site).to_string()),
+                    };
+
+                    output.push(PpToken { kind, text, expansion: Some(site), ..token });
+                }
+                MacroKind::Object => {
+                    let hideset = with_name(&token.hideset, &token.text);
+                    let body = self.substitute(&definition, &[], &token, hideset, site);
+
+                    for expanded in body.into_iter().rev() {
+                        input.push_front(expanded);
+                    }
+                }
+                MacroKind::Function { params, variadic } => {
+                    let Some(open) = input.iter().position(|t| t.kind != PpKind::Newline)
+                        .filter(|&open| input[open].is("(")) else {
+                        output.push(token);
+                        continue;
+                    };
+
+                    let Some((mut args, close)) = Self::collect_arguments(&mut input, open, params.len(), *variadic) else {
+                        let site = token.expansion.clone().unwrap_or_else(|| token.span.clone());
+                        self.errors.push(PreprocessorError::UnterminatedInvocation(site, token.text.clone()));
+                        output.push(token);
+                        continue;
+                    };
+
+                    let site = span_between(
+                        token.expansion.as_ref().unwrap_or(&token.span),
+                        close.expansion.as_ref().unwrap_or(&close.span))
+                        .with_expansions(token.expansion.as_ref().map_or_else(Vec::new, |site| site.expansions().to_vec()));
+
+                    // `F()` passes one empty argument, which is none at all for `F` without parameters
+                    if params.is_empty() && args.len() == 1 && args[0].is_empty() {
+                        args.clear();
+                    }
+
+                    if *variadic && args.len() == params.len() - 1 {
+                        args.push(Vec::new());
+                    }
+
+                    if args.len() != params.len() {
+                        let expected = params.len() - *variadic as usize;
+                        self.errors.push(PreprocessorError::WrongArgumentCount(site, token.text.clone(), expected, args.len()));
+                        continue;
+                    }
+
+                    let hideset = with_name(&intersection(&token.hideset, &close.hideset), &token.text);
+                    let body = self.substitute(&definition, &args, &token, hideset, site);
+
+                    for expanded in body.into_iter().rev() {
+                        input.push_front(expanded);
+                    }
+                }
+            }
+        }
+
+        output
+    }
+
+    // Takes a macro's arguments off the front of `input`, where `open` is the index of the
+    // `(`. Returns them with the closing `)`, or None if the list is never closed.
+    fn collect_arguments(input: &mut VecDeque<PpToken>, open: usize, params: usize, variadic: bool) -> Option<(Vec<Vec<PpToken>>, PpToken)> {
+        let mut depth = 0;
+        let close = input.iter().enumerate().skip(open + 1).find(|(_, token)| {
+            if token.is("(") {
+                depth += 1;
+            } else if token.is(")") {
+                if depth == 0 {
+                    return true;
+                }
+                depth -= 1;
+            }
+            false
+        })?.0;
+
+        let mut tokens: Vec<PpToken> = input.drain(..=close).skip(open + 1).collect();
+        let close = tokens.pop().expect("the closing parenthesis was found");
+        let mut args = vec![Vec::new()];
+        let mut depth = 0;
+        let mut space = false;
+
+        for mut token in tokens {
+            if token.kind == PpKind::Newline {
+                space = true;
+                continue;
+            }
+
+            if token.is("(") {
+                depth += 1;
+            } else if token.is(")") {
+                depth -= 1;
+            } else if token.is(",") && depth == 0 && !(variadic && args.len() == params) {
+                args.push(Vec::new());
+                continue;
+            }
+
+            token.has_space |= space;
+            space = false;
+            args.last_mut().expect("there is always an argument").push(token);
+        }
+
+        Some((args, close))
+    }
+
+    // Replaces the parameters in a macro body, handling `#` and `##`. Each token records the
+    // macros it came through, on top of the ones `site` already carries.
+    fn substitute(&mut self, definition: &Macro, args: &[Vec<PpToken>], invocation: &PpToken, hideset: HideSet, site: Span) -> Vec<PpToken> {
+        let body = &definition.body;
+        // tokens with whether they were written in the body rather than passed as arguments
+        let mut result: Vec<(PpToken, bool)> = Vec::new();
+        let mut index = 0;
+
+        while index < body.len() {
+            let token = &body[index];
+            let next = body.get(index + 1);
+
+            if matches!(definition.kind, MacroKind::Function { .. }) && token.is_hash()
+                && let Some(param) = next.and_then(|next| definition.param_index(next)) {
+                result.push((PpToken { has_space: token.has_space, ..stringify(&args[param], token) }, true));
+                index += 2;
+                continue;
+            }
+
+            if token.is_hash_hash() && let Some(next) = next {
+                let (mut right, right_in_body) = match definition.param_index(next) {
+                    Some(param) if args[param].is_empty() => (vec![placemarker(next)], false),
+                    Some(param) => (args[param].clone(), false),
+                    None => (vec![next.clone()], true),
+                };
+
+                let (left, left_in_body) = result.pop().unwrap_or_else(|| (placemarker(token), true));
+                let first = right.remove(0);
+                let pasted = self.paste(left, first, &site);
+                result.push((pasted, left_in_body));
+                result.extend(right.into_iter().map(|token| (token, right_in_body)));
+                index += 2;
+                continue;
+            }
+
+            match definition.param_index(token) {
+                Some(param) => {
+                    let mut arg = if next.is_some_and(PpToken::is_hash_hash) {
+                        args[param].clone()
+                    } else {
+                        self.expand(args[param].clone())
+                    };
+
+                    match arg.first_mut() {
+                        Some(first) => first.has_space = token.has_space,
+                        None => arg.push(placemarker(token)),
+                    }
+
+                    result.extend(arg.into_iter().map(|token| (token, false)));
+                }
+                None => result.push((token.clone(), true)),
+            }
+
+            index += 1;
+        }
+
+        let mut result: Vec<PpToken> = result.into_iter()
+            .filter(|(token, _)| token.kind != PpKind::Placemarker)
+            .map(|(token, in_body)| {
+                let expansions = if in_body {
+                    let step = Expansion { name: invocation.text.clone(), definition: token.span.clone() };
+                    std::iter::once(step).chain(site.expansions().iter().cloned()).collect()
+                } else {
+                    // arguments keep the macros they were expanded through themselves
+                    token.expansion.as_ref().unwrap_or(&site).expansions().to_vec()
+                };
+
+                PpToken {
+                    hideset: union(&token.hideset, &hideset),
+                    expansion: Some(site.clone().with_expansions(expansions)),
+                    ..token
+                }
+            })
+            .collect();
+
+        if let Some(first) = result.first_mut() {
+            first.has_space = invocation.has_space;
+        }
+
+        result
+    }
+
+    fn paste(&mut self, left: PpToken, right: PpToken, site: &Span) -> PpToken {
+        if left.kind == PpKind::Placemarker {
+            return PpToken { has_space: left.has_space, ..right };
+        }
+
+        if right.kind == PpKind::Placemarker {
+            return left;
+        }
+
+        let text = format!("{}{}", left.text, right.text);
+        let tokens = tokenize(&text, left.span.source());
+
+        match &tokens[..] {
+            [pasted, eof] if eof.kind == PpKind::Eof && pasted.leading == 0 && eof.leading == 0 =>
+                PpToken { kind: pasted.kind, text, ..left },
+            _ => {
+                self.errors.push(PreprocessorError::InvalidPaste(site.clone(), text));
+                left
+            }
+        }
+    }
+
+    fn presumed_name(&self) -> String {
+        self.files.last().map_or_else(String::new, |file| file.presumed.clone())
+    }
+
+    fn presumed_line(&self, site: &Span) -> i64 {
+        match self.files.last() {
+            Some(file) if site.source() == &file.unit => file.line(site.start()) + file.line_delta,
+            _ => 0,
+        }
+    }
+
+    fn directive(&mut self, hash: &PpToken, line: &[PpToken]) {
+        // a lone `#` is the null directive
+        let Some(name) = line.first() else {
+            return;
+        };
+
+        let span = span_between(&hash.span, &line.last().unwrap_or(name).span);
+        let heading = span_between(&hash.span, &name.span);
+        let rest = &line[1..];
+
+        match name.text.as_str() {
+            "if" | "ifdef" | "ifndef" => {
+                let parent_active = self.active();
+                let active = parent_active && match name.text.as_str() {
+                    "if" => self.condition(rest, &span),
+                    directive => self.defined_condition(rest, &span, directive == "ifdef"),
+                };
+
+                self.push_conditional(Conditional { span: heading, parent_active, taken: active, active, seen_else: false });
+            }
+            "elif" | "elifdef" | "elifndef" => {
+                let Some(conditional) = self.open_conditional(&heading) else {
+                    return;
+                };
+
+                if !conditional.parent_active || conditional.taken {
+                    conditional.active = false;
+                    return;
+                }
+
+                let active = match name.text.as_str() {
+                    "elif" => self.condition(rest, &span),
+                    directive => self.defined_condition(rest, &span, directive == "elifdef"),
+                };
+
+                if let Some(conditional) = self.open_conditional(&heading) {
+                    conditional.active = active;
+                    conditional.taken = active;
+                }
+            }
+            "else" => {
+                if let Some(conditional) = self.open_conditional(&heading) {
+                    conditional.active = conditional.parent_active && !conditional.taken;
+                    conditional.taken = true;
+                    conditional.seen_else = true;
+                }
+            }
+            "endif" => {
+                let conditionals = self.files.last_mut().map(|file| &mut file.conditionals);
+
+                if conditionals.and_then(|conditionals| conditionals.pop()).is_none() {
+                    self.errors.push(PreprocessorError::UnmatchedConditional(heading));
+                }
+            }
+            _ if !self.active() => {}
+            "define" => self.define(rest, &span),
+            "undef" => match rest.first() {
+                Some(macro_name) if macro_name.kind == PpKind::Identifier => {
+                    self.macros.remove(&macro_name.text);
+                }
+                _ => self.errors.push(PreprocessorError::InvalidDirective(span, "macro names must be identifiers".to_string())),
+            },
+            "include" => self.include(rest, &span),
+            "error" => {
+                let mut message = String::new();
+
+                for token in rest {
+                    if token.has_space && !message.is_empty() {
+                        message.push(' ');
+                    }
+
+                    message.push_str(&token.text);
+                }
+
+                self.errors.push(PreprocessorError::ErrorDirective(span, message));
+            }
+            "line" => self.line(rest, &span),
+            "pragma" => {}
+            _ => self.errors.push(PreprocessorError::UnknownDirective(heading)),
+        }
+    }
+
+    fn push_conditional(&mut self, conditional: Conditional) {
+        if let Some(file) = self.files.last_mut() {
+            file.conditionals.push(conditional);
+        }
+    }
+
+    // The innermost conditional, if it can still take an #elif or #else.
+    fn open_conditional(&mut self, span: &Span) -> Option<&mut Conditional> {
+        let open = self.files.last()
+            .and_then(|file| file.conditionals.last())
+            .is_some_and(|conditional| !conditional.seen_else);
+
+        if !open {
+            self.errors.push(PreprocessorError::UnmatchedConditional(span.clone()));
+            return None;
+        }
+
+        self.files.last_mut().and_then(|file| file.conditionals.last_mut())
+    }
+
+    fn defined_condition(&mut self, rest: &[PpToken], span: &Span, defined: bool) -> bool {
+        match rest.first() {
+            Some(name) if name.kind == PpKind::Identifier => self.macros.contains_key(&name.text) == defined,
+            _ => {
+                self.errors.push(PreprocessorError::InvalidDirective(span.clone(), "macro names must be identifiers".to_string()));
+                false
+            }
+        }
+    }
+
+    fn condition(&mut self, tokens: &[PpToken], span: &Span) -> bool {
+        let mut replaced = Vec::new();
+        let mut index = 0;
+
+        // `defined` is resolved before macro expansion could change its operand
+        while index < tokens.len() {
+            let token = &tokens[index];
+
+            if token.kind != PpKind::Identifier || token.text != "defined" {
+                replaced.push(token.clone());
+                index += 1;
+                continue;
+            }
+
+            let (name, consumed) = match (tokens.get(index + 1), tokens.get(index + 2), tokens.get(index + 3)) {
+                (Some(open), Some(name), Some(close)) if open.is("(") && close.is(")") => (name, 4),
+                (Some(name), _, _) if !name.is("(") => (name, 2),
+                _ => {
+                    self.errors.push(PreprocessorError::InvalidCondition(token.span.clone(), "expected a macro name after 'defined'".to_string()));
+                    return false;
+                }
+            };
+
+            if name.kind != PpKind::Identifier {
+                self.errors.push(PreprocessorError::InvalidCondition(name.span.clone(), "macro names must be identifiers".to_string()));
+                return false;
+            }
+
+            let value = if self.macros.contains_key(&name.text) { "1" } else { "0" };
+            replaced.push(PpToken::new(PpKind::Number, value, span_between(&token.span, &tokens[index + consumed - 1].span)));
+            index += consumed;
+        }
+
+        let mut expanded = self.expand(replaced);
+
+        // report problems where the tokens were used rather than where the macro was defined
+        for token in &mut expanded {
+            if let Some(site) = token.expansion.take() {
+                token.span = site;
+            }
+        }
+
+        match evaluate(&expanded, span) {
+            Ok(value) => value.is_true(),
+            Err(err) => {
+                self.errors.push(err);
+                false
+            }
+        }
+    }
+
+    fn define(&mut self, rest: &[PpToken], span: &Span) {
+        let invalid = |message: &str| PreprocessorError::InvalidDirective(span.clone(), message.to_string());
+
+        let Some(name) = rest.first().filter(|name| name.kind == PpKind::Identifier) else {
+            self.errors.push(invalid("macro names must be identifiers"));
+            return;
+        };
+
+        if name.text == "defined" {
+            self.errors.push(invalid("'defined' cannot be used as a macro name"));
+            return;
+        }
+
+        let mut body = &rest[1..];
+        let mut kind = MacroKind::Object;
+
+        // only a `(` written right after the name makes a function-like macro
+        if body.first().is_some_and(|open| open.is("(") && !open.has_space) {
+            let mut params = Vec::new();
+            let mut variadic = false;
+            let mut index = 1;
+
+            loop {
+                match body.get(index) {
+                    Some(token) if token.is(")") && params.is_empty() => break,
+                    Some(token) if token.is("...") => {
+                        variadic = true;
+                        params.push("__VA_ARGS__".to_string());
+                        index += 1;
+                    }
+                    Some(token) if token.kind == PpKind::Identifier => {
+                        if params.contains(&token.text) {
+                            self.errors.push(invalid(&format!("duplicate macro parameter '{}'", token.text)));
+                            return;
+                        }
+                        params.push(token.text.clone());
+                        index += 1;
+                    }
+                    _ => {
+                        self.errors.push(invalid("expected a parameter name"));
+                        return;
+                    }
+                }
+
+                match body.get(index) {
+                    Some(token) if token.is(")") => break,
+                    Some(token) if token.is(",") && !variadic => index += 1,
+                    _ => {
+                        self.errors.push(invalid("expected ')' in macro parameter list"));
+                        return;
+                    }
+                }
+            }
+
+            body = &body[index + 1..];
+            kind = MacroKind::Function { params, variadic };
+        }
+
+        if body.first().is_some_and(PpToken::is_hash_hash) || body.last().is_some_and(PpToken::is_hash_hash) {
+            self.errors.push(invalid("'##' cannot appear at either end of a macro expansion"));
+            return;
+        }
+
+        let mut definition = Macro { kind, body: body.to_vec(), span: name.span.clone() };
+
+        if let Some(first) = definition.body.first_mut() {
+            first.has_space = false;
+        }
+
+        if matches!(definition.kind, MacroKind::Function { .. }) {
+            for (index, token) in definition.body.iter().enumerate() {
+                if token.is_hash() && definition.body.get(index + 1).and_then(|next| definition.param_index(next)).is_none() {
+                    self.errors.push(invalid("'#' is not followed by a macro parameter"));
+                    return;
+                }
+            }
+        }
+
+        if let Some(previous) = self.macros.get(&name.text) && !previous.same_definition(&definition) {
+            self.errors.push(PreprocessorError::MacroRedefined(name.span.clone(), name.text.clone(), previous.span.clone()));
+        }
+
+        self.macros.insert(name.text.clone(), definition);
+    }
+
+    fn include(&mut self, rest: &[PpToken], span: &Span) {
+        // a header name that isn't spelled out directly comes from macro expansion
+        let tokens = match rest.first() {
+            Some(first) if first.kind == PpKind::StringLiteral || first.is("<") => rest.to_vec(),
+            _ => self.expand(rest.to_vec()),
+        };
+
+        let (name, quoted) = match &tokens[..] {
+            [literal, ..] if literal.kind == PpKind::StringLiteral && literal.text.starts_with('"') =>
+                (literal.text[1..literal.text.len() - 1].to_string(), true),
+            [open, inner @ ..] if open.is("<") && inner.iter().any(|token| token.is(">")) => {
+                let mut name = String::new();
+
+                for token in inner.iter().take_while(|token| !token.is(">")) {
+                    if token.has_space && !name.is_empty() {
+                        name.push(' ');
+                    }
+                    name.push_str(&token.text);
+                }
+
+                (name, false)
+            }
+            _ => {
+                self.errors.push(PreprocessorError::InvalidDirective(span.clone(), "#include expects \"FILENAME\" or <FILENAME>".to_string()));
+                return;
+            }
+        };
+
+        let written = if quoted { format!("\"{}\"", name) } else { format!("<{}>", name) };
+
+        if self.files.len() >= MAX_INCLUDE_DEPTH {
+            self.errors.push(PreprocessorError::IncludeTooDeep(span.clone()));
+            return;
+        }
+
+        let directory = self.files.last()
+            .and_then(|file| file.path.parent())
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        let candidates = quoted.then_some(&directory).into_iter().chain(&self.options.include_paths);
+        let found = candidates
+            .map(|directory| directory.join(&name))
+            .find(|path| path.is_file())
+            .and_then(|path| std::fs::read_to_string(&path).ok().map(|text| (path, text)));
+
+        let Some((path, text)) = found else {
+            self.errors.push(PreprocessorError::IncludeNotFound(span.clone(), written));
+            return;
+        };
+
+        let unit = Id::Unit(path.display().to_string());
+
+        if !self.included.iter().any(|file| file.id == unit) {
+            let mut file = SourceFile::from_string(unit.clone(), &text);
+            file.filename = path.display().to_string();
+            self.included.push(file);
+        }
+
+        self.included_at.entry(unit.clone()).or_insert_with(|| span.clone());
+        self.run_file(unit, path, &text);
+    }
+
+    fn line(&mut self, rest: &[PpToken], span: &Span) {
+        let tokens = self.expand(rest.to_vec());
+
+        let (number, name) = match &tokens[..] {
+            [number] if number.kind == PpKind::Number => (number, None),
+            [number, name] if number.kind == PpKind::Number && name.kind == PpKind::StringLiteral => (number, Some(name)),
+            _ => {
+                self.errors.push(PreprocessorError::InvalidDirective(span.clone(), "#line expects a line number and an optional file name".to_string()));
+                return;
+            }
+        };
+
+        let Some(line) = number.text.parse::<i64>().ok().filter(|_| number.text.bytes().all(|c| c.is_ascii_digit())) else {
+            self.errors.push(PreprocessorError::InvalidDirective(span.clone(), format!("'{}' is not a valid line number", number.text)));
+            return;
+        };
+
+        if let Some(file) = self.files.last_mut() {
+            // the line after the directive gets the given number
+            file.line_delta = line - (file.line(span.start()) + 1);
+
+            if let Some(name) = name {
+                file.presumed = name.text[1..name.text.len() - 1].to_string();
+            }
+        }
+    }
+}
+
+fn placemarker(at: &PpToken) -> PpToken {
+    PpToken::new(PpKind::Placemarker, "", at.span.clone())
+}
+
+fn stringify(arg: &[PpToken], hash: &PpToken) -> PpToken {
+    let mut text = String::new();
+
+    for token in arg {
+        if token.has_space && !text.is_empty() {
+            text.push(' ');
+        }
+
+        match token.kind {
+            PpKind::StringLiteral | PpKind::CharConstant => text.push_str(&token.text.replace('\\', "\\\\").replace('"', "\\\"")),
+            _ => text.push_str(&token.text),
+        }
+    }
+
+    PpToken::new(PpKind::StringLiteral, format!("\"{}\"", text), hash.span.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use super::*;
+
+    fn run(text: &str) -> Preprocessed {
+        run_with(text, &PreprocessorOptions::default())
+    }
+
+    fn run_with(text: &str, options: &PreprocessorOptions) -> Preprocessed {
+        let mut file = SourceFile::from_string(Id::Unit("test.c".to_string()), text);
+        file.filename = "test.c".to_string();
+        preprocess(&file, options)
+    }
+
+    fn words(text: &str) -> Vec<String> {
+        tokenize(text, &Id::Main).into_iter()
+            .filter(|token| !matches!(token.kind, PpKind::Newline | PpKind::Eof))
+            .map(|token| token.text)
+            .collect()
+    }
+
+    fn output(text: &str) -> Vec<String> {
+        let result = run(text);
+        assert_eq!(result.errors, vec![]);
+        words(&result.text)
+    }
+
+    #[test]
+    fn text_without_directives_is_unchanged() {
+        let text = "int main(void) { /* hi */\n    return 0;\n}\n";
+
+        assert_eq!(run(text).text, text);
+    }
+
+    #[test]
+    fn object_and_function_like_macros() {
+        assert_eq!(output("#define N 4\n#define ADD(a, b) ((a) + (b))\nint x = ADD(N, 1);\n"),
+            words("int x = ((4) + (1));"));
+    }
+
+    #[test]
+    fn stringify_and_paste() {
+        assert_eq!(output("#define STR(x) #x\n#define CAT(a, b) a ## b\nCAT(foo, 1) STR(a \"b\")\nCAT(, x)\n"),
+            words("foo1 \"a \\\"b\\\"\" x"));
+    }
+
+    #[test]
+    fn recursion_is_stopped_by_hidesets() {
+        assert_eq!(output("#define f(x) x + f(x)\n#define g f\ng(1)\n"), words("1 + f(1)"));
+    }
+
+    #[test]
+    fn variadic_macros() {
+        assert_eq!(output("#define CALL(f, ...) f(__VA_ARGS__)\nCALL(g, 1, 2) CALL(h)\n"), words("g(1, 2) h()"));
+    }
+
+    #[test]
+    fn invocation_spanning_lines() {
+        assert_eq!(output("#define F(x) [x]\nF\n(\n1\n)\n"), words("[1]"));
+    }
+
+    #[test]
+    fn conditionals() {
+        let text = "#define A 2\n#if A > 1 && defined(A)\nyes\n#elif 1 / 0\nno\n#else\nno\n#endif\n#ifndef B\nb\n#endif\n";
+
+        assert_eq!(output(text), words("yes b"));
+    }
+
+    #[test]
+    fn skipped_groups_keep_their_lines() {
+        let result = run("#if 0\nx\ny\n#endif\nz\n");
+
+        assert_eq!(result.text, "\n\n\n\nz\n");
+    }
+
+    #[test]
+    fn builtin_line_and_file() {
+        assert_eq!(output("__LINE__\n#line 100 \"other.c\"\n__LINE__ __FILE__\n"), words("1 100 \"other.c\""));
+    }
+
+    #[test]
+    fn command_line_defines() {
+        let options = PreprocessorOptions { defines: vec![("X".to_string(), "42".to_string())], ..Default::default() };
+
+        assert_eq!(words(&run_with("X __STDC_VERSION__\n", &options).text), words("42 201710L"));
+    }
+
+    #[test]
+    fn spans_map_to_the_source_and_the_use_site() {
+        let text = "#define N 42\nint x = N;\n";
+        let result = run(text);
+        let at = |needle: &str| {
+            let start = result.text.find(needle).unwrap();
+            result.map.map_span(&Span::new_with_unit(start, needle.len(), result.map.output.clone()))
+        };
+
+        let unit = Id::Unit("test.c".to_string());
+        let expansion = Expansion { name: "N".to_string(), definition: Span::new_with_unit(10, 2, unit.clone()) };
+        assert_eq!(at("int"), Span::new_with_unit(13, 3, unit.clone()));
+        assert_eq!(at("42"), Span::new_with_unit(21, 1, unit).with_expansions(vec![expansion]));
+    }
+
+    #[test]
+    fn expansions_record_each_macro_innermost_first() {
+        let text = "#define INNER(x) x + 1\n#define OUTER INNER(2)\nOUTER\n";
+        let result = run(text);
+        let start = result.text.find('+').unwrap();
+        let span = result.map.map_span(&Span::new_with_unit(start, 1, result.map.output.clone()));
+        let names: Vec<&str> = span.expansions().iter().map(|expansion| expansion.name.as_str()).collect();
+
+        assert_eq!(span.range(), 46..51);
+        assert_eq!(names, vec!["INNER", "OUTER"]);
+        assert_eq!(&text[span.expansions()[0].definition.range()], "+");
+        assert_eq!(&text[span.expansions()[1].definition.range()], "INNER");
+    }
+
+    #[test]
+    fn line_continuations() {
+        let text = "#define SUM(a, b) \\\n    ((a) + \\\n     (b))\nint x = SU\\\nM(1, 2);\n";
+        let result = run(text);
+
+        assert_eq!(words(&result.text), words("int x = ((1) + (2));"));
+
+        let at = |needle: &str| {
+            let start = result.text.find(needle).unwrap();
+            result.map.map_span(&Span::new_with_unit(start, needle.len(), result.map.output.clone()))
+        };
+
+        assert_eq!(&text[at("int x").range()], "int x");
+        assert_eq!(&text[at("+").expansions()[0].definition.range()], "+");
+        assert_eq!(&text[at("+").range()], "SU\\\nM(1, 2)");
+    }
+
+    #[test]
+    fn trigraphs() {
+        let text = "??=define A(x) x??(0??)\nA(a) ??!??! b\n";
+        let options = PreprocessorOptions { trigraphs: true, ..PreprocessorOptions::default() };
+        let result = run_with(text, &options);
+
+        assert_eq!(words(&result.text), words("a[0] || b"));
+
+        let start = result.text.find('|').unwrap();
+        let span = result.map.map_span(&Span::new_with_unit(start, 2, result.map.output.clone()));
+        assert_eq!(&text[span.range()], "??!??!");
+
+        assert_eq!(run(text).text, text);
+    }
+
+    #[test]
+    fn include_searches_the_include_paths() {
+        let directory = std::env::temp_dir().join(format!("niamc-pp-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("answer.h"), "#define ANSWER 42\nint answer;\n").unwrap();
+
+        let options = PreprocessorOptions { include_paths: vec![directory.clone()], ..Default::default() };
+        let result = run_with("#include <answer.h>\nint x = ANSWER;\n", &options);
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(result.errors, vec![]);
+        assert_eq!(words(&result.text), words("int answer; int x = 42;"));
+        assert_eq!(result.included.iter().filter(|file| file.filename.ends_with("answer.h")).count(), 1);
+
+        let header = result.included.iter().find(|file| file.filename.ends_with("answer.h")).unwrap();
+        assert_eq!(result.included_at[&header.id].range(), 0..19);
+    }
+
+    #[test]
+    fn errors() {
+        assert_matches!(&run("#include \"missing.h\"\n").errors[..], [PreprocessorError::IncludeNotFound(_, name)] if name == "\"missing.h\"");
+        assert_matches!(&run("#error stop here\n").errors[..], [PreprocessorError::ErrorDirective(_, message)] if message == "stop here");
+        assert_matches!(&run("#if 1\n").errors[..], [PreprocessorError::UnterminatedConditional(_)]);
+        assert_matches!(&run("#endif\n").errors[..], [PreprocessorError::UnmatchedConditional(_)]);
+        assert_matches!(&run("#defne X\n").errors[..], [PreprocessorError::UnknownDirective(_)]);
+        assert_matches!(&run("#define F(a, b) a\nF(1)\n").errors[..], [PreprocessorError::WrongArgumentCount(_, name, 2, 1)] if name == "F");
+        assert_matches!(&run("#define F(a) a\nF(1\n").errors[..], [PreprocessorError::UnterminatedInvocation(..)]);
+        assert_matches!(&run("#define C(a, b) a ## b\nC(+, /)\n").errors[..], [PreprocessorError::InvalidPaste(_, text)] if text == "+/");
+        assert_matches!(&run("#define X 1\n#define X 2\n").errors[..], [PreprocessorError::MacroRedefined(..)]);
+        assert_eq!(run("#define X 1\n#define X 1\n").errors, vec![]);
+    }
+
+    #[test]
+    fn error_directives_show_their_line() {
+        let text = "int x;\n#error stop here\n";
+        let diagnostic = run(text).errors[0].to_diagnostic();
+
+        assert_eq!(diagnostic.labels.len(), 1);
+        assert_eq!(&text[diagnostic.labels[0].span.range()], "#error stop here");
+    }
+
+    #[test]
+    fn skipped_groups_ignore_unknown_directives() {
+        assert_eq!(run("#if 0\n#bogus\n#error no\n#endif\n").errors, vec![]);
+    }
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(20744), (2026, 10, 18));
+    }
+}
@@ -0,0 +1,257 @@
+use ariadne::Span as ASpan;
+use common::diagnostics::Diagnostic;
+use common::source_file::Id;
+use common::span::Span;
+
+#[derive(Debug, Clone)]
+struct Segment {
+    output_start: usize,
+    output_len: usize,
+    // For exact segments every output byte maps to the byte at the same distance into
+    // `origin`; otherwise the whole segment maps to all of `origin`.
+    origin: Span,
+    exact: bool,
+}
+
+impl Segment {
+    fn output_end(&self) -> usize {
+        self.output_start + self.output_len
+    }
+
+    fn map_start(&self, offset: usize) -> usize {
+        if self.exact {
+            self.origin.range().start + (offset - self.output_start)
+        } else {
+            self.origin.range().start
+        }
+    }
+
+    fn map_end(&self, offset: usize) -> usize {
+        if self.exact {
+            self.origin.range().start + (offset - self.output_start)
+        } else {
+            self.origin.range().end
+        }
+    }
+
+    fn unit(&self) -> Id {
+        self.origin.source().clone()
+    }
+
+    // A span in the segment's unit, carrying the macros the segment's text came through.
+    fn span(&self, start: usize, len: usize) -> Span {
+        Span::new_with_unit(start, len, self.unit()).with_expansions(self.origin.expansions().to_vec())
+    }
+}
+
+// Maps spans in the preprocessed text back to where that text came from: the original
+// bytes for text copied from a file, the use site for text produced by a macro.
+#[derive(Debug, Clone)]
+pub struct SpanMap {
+    // unit of the preprocessed text, whose spans get mapped
+    pub output: Id,
+    // where anything that can't be placed ends up, normally the start of the main file
+    fallback: Span,
+    segments: Vec<Segment>,
+}
+
+impl SpanMap {
+    pub fn new(output: Id, fallback: Span) -> SpanMap {
+        SpanMap { output, fallback, segments: Vec::new() }
+    }
+
+    pub fn push_exact(&mut self, output_start: usize, origin: Span) {
+        if origin.range().is_empty() {
+            return;
+        }
+
+        if let Some(last) = self.segments.last_mut()
+            && last.exact
+            && last.output_end() == output_start
+            && last.origin.range().end == origin.range().start
+            && last.unit() == Self::unit_of(&origin) {
+            last.output_len += origin.end() - origin.start();
+            last.origin = Span::new_with_unit(last.origin.range().start, last.output_len, last.unit());
+            return;
+        }
+
+        self.segments.push(Segment { output_start, output_len: origin.end() - origin.start(), origin, exact: true });
+    }
+
+    pub fn push_expanded(&mut self, output_start: usize, output_len: usize, origin: Span) {
+        if output_len == 0 {
+            return;
+        }
+
+        if let Some(last) = self.segments.last_mut()
+            && !last.exact
+            && last.output_end() == output_start
+            && last.origin == origin {
+            last.output_len += output_len;
+            return;
+        }
+
+        self.segments.push(Segment { output_start, output_len, origin, exact: false });
+    }
+
+    fn unit_of(span: &Span) -> Id {
+        span.source().clone()
+    }
+
+    // The segment holding the byte at `offset`, or for an end position the byte before it.
+    fn segment(&self, offset: usize, end: bool) -> Option<&Segment> {
+        let index = self.segments.partition_point(|segment|
+            if end { segment.output_end() < offset } else { segment.output_end() <= offset });
+
+        self.segments.get(index)
+            .filter(|segment| segment.output_start < offset || (!end && segment.output_start == offset))
+            .or_else(|| if end && offset == 0 { self.segments.first() } else { None })
+    }
+
+    pub fn map_span(&self, span: &Span) -> Span {
+        if span.source() != &self.output {
+            return span.clone();
+        }
+
+        let range = span.range();
+
+        if range.is_empty() {
+            // insertion points and end of file belong after the text before them
+            return match self.segment(range.start, true).or(self.segments.last()) {
+                Some(segment) => {
+                    let offset = segment.map_end(range.start.min(segment.output_end()));
+                    segment.span(offset, 0)
+                }
+                None => self.fallback.clone(),
+            };
+        }
+
+        let Some(first) = self.segment(range.start, false) else {
+            return self.fallback.clone();
+        };
+
+        let start = first.map_start(range.start);
+
+        match self.segment(range.end, true) {
+            Some(last) if last.unit() == first.unit() && last.map_end(range.end) >= start =>
+                first.span(start, last.map_end(range.end) - start),
+            _ => first.span(start, first.map_end(first.output_end().min(range.end)) - start),
+        }
+    }
+
+    // Where a fix-it at `span` edits the original files, if it can. Text that came out of a
+    // macro can't be edited at the macro's use, so only text copied byte for byte qualifies,
+    // as does an insertion right after a whole expansion, which goes after its use.
+    pub fn map_fix(&self, span: &Span) -> Option<Span> {
+        if span.source() != &self.output {
+            return Some(span.clone());
+        }
+
+        let range = span.range();
+
+        if range.is_empty() {
+            let index = self.segments.partition_point(|segment| segment.output_end() < range.start);
+            let segment = self.segments.get(index).filter(|segment| segment.output_start < range.start)?;
+
+            if segment.exact {
+                return Some(segment.span(segment.map_end(range.start), 0));
+            }
+
+            let same_use = |next: &Segment| !next.exact && next.origin.range() == segment.origin.range() && next.unit() == segment.unit();
+            let last = range.start == segment.output_end() && !self.segments.get(index + 1).is_some_and(same_use);
+
+            return last.then(|| Span::new_with_unit(segment.origin.end(), 0, segment.unit()));
+        }
+
+        let exact = self.segments.iter()
+            .filter(|segment| segment.output_start < range.end && segment.output_end() > range.start)
+            .all(|segment| segment.exact);
+
+        (exact && self.segment(range.start, false).is_some()).then(|| self.map_span(span))
+    }
+
+    // Moves a diagnostic about the preprocessed text back to the original files, dropping the
+    // fix-its that can't be made there.
+    pub fn map_diagnostic(&self, mut diagnostic: Diagnostic) -> Diagnostic {
+        diagnostic.fixes.retain_mut(|fix| match self.map_fix(&fix.span) {
+            Some(span) => {
+                fix.span = span;
+                true
+            }
+            None => false,
+        });
+
+        diagnostic.map_spans(|span| self.map_span(span))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::diagnostics::FixIt;
+    use common::span::Expansion;
+    use super::*;
+
+    fn unit(name: &str) -> Id {
+        Id::Unit(name.to_string())
+    }
+
+    fn map() -> SpanMap {
+        // output: "int x" from a.c at 10, then " 42" from a macro used at a.c 20..25
+        let mut map = SpanMap::new(unit("out"), Span::new_with_unit(0, 0, unit("a.c")));
+        map.push_exact(0, Span::new_with_unit(10, 3, unit("a.c")));
+        map.push_exact(3, Span::new_with_unit(13, 2, unit("a.c")));
+        map.push_expanded(5, 3, Span::new_with_unit(20, 5, unit("a.c")));
+        map
+    }
+
+    #[test]
+    fn exact_text_maps_byte_for_byte() {
+        assert_eq!(map().map_span(&Span::new_with_unit(1, 3, unit("out"))), Span::new_with_unit(11, 3, unit("a.c")));
+    }
+
+    #[test]
+    fn expanded_text_maps_to_use_site() {
+        assert_eq!(map().map_span(&Span::new_with_unit(6, 2, unit("out"))), Span::new_with_unit(20, 5, unit("a.c")));
+    }
+
+    #[test]
+    fn empty_spans_map_to_the_end_of_what_precedes_them() {
+        assert_eq!(map().map_span(&Span::new_with_unit(5, 0, unit("out"))), Span::new_with_unit(15, 0, unit("a.c")));
+        assert_eq!(map().map_span(&Span::new_with_unit(8, 0, unit("out"))), Span::new_with_unit(25, 0, unit("a.c")));
+    }
+
+    #[test]
+    fn fixes_only_edit_copied_text() {
+        let expansion = Expansion { name: "N".to_string(), definition: Span::new_with_unit(8, 2, unit("a.c")) };
+        let mut map = map();
+        // output " 1 1" at 8..12, one token at a time, from a macro used at a.c 30..31
+        map.push_expanded(9, 1, Span::new_with_unit(30, 1, unit("a.c")).with_expansions(vec![expansion.clone()]));
+        map.push_expanded(11, 1, Span::new_with_unit(30, 1, unit("a.c")).with_expansions(vec![expansion]));
+
+        assert_eq!(map.map_fix(&Span::new_with_unit(1, 3, unit("out"))), Some(Span::new_with_unit(11, 3, unit("a.c"))));
+        assert_eq!(map.map_fix(&Span::new_with_unit(5, 0, unit("out"))), Some(Span::new_with_unit(15, 0, unit("a.c"))));
+        assert_eq!(map.map_fix(&Span::new_with_unit(4, 2, unit("out"))), None);
+        // after the first token of the expansion, and after the whole of it
+        assert_eq!(map.map_fix(&Span::new_with_unit(10, 0, unit("out"))), None);
+        assert_eq!(map.map_fix(&Span::new_with_unit(12, 0, unit("out"))), Some(Span::new_with_unit(31, 0, unit("a.c"))));
+    }
+
+    #[test]
+    fn mapped_diagnostics_drop_fixes_inside_expansions() {
+        let diagnostic = Diagnostic::error(Span::new_with_unit(6, 1, unit("out")), "expected ';'")
+            .with_fix(FixIt::insert(Span::new_with_unit(6, 0, unit("out")), ";"))
+            .with_fix(FixIt::insert(Span::new_with_unit(3, 0, unit("out")), ";"));
+        let mapped = map().map_diagnostic(diagnostic);
+
+        assert_eq!(mapped.span, Span::new_with_unit(20, 5, unit("a.c")));
+        assert_eq!(mapped.fixes.len(), 1);
+        assert_eq!(mapped.fixes[0].span, Span::new_with_unit(13, 0, unit("a.c")));
+    }
+
+    #[test]
+    fn other_units_are_untouched() {
+        let span = Span::new_with_unit(1, 1, unit("b.c"));
+
+        assert_eq!(map().map_span(&span), span);
+    }
+}
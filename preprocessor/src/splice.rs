@@ -0,0 +1,200 @@
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy)]
+struct Piece {
+    spliced_start: usize,
+    original_start: usize,
+    len: usize,
+    // a trigraph is one byte in the spliced text and three in the original
+    trigraph: bool,
+}
+
+impl Piece {
+    fn spliced_end(&self) -> usize {
+        self.spliced_start + self.len
+    }
+
+    fn original_len(&self) -> usize {
+        if self.trigraph { 3 } else { self.len }
+    }
+}
+
+// A file's text after translation phases 1 and 2: trigraphs replaced, if enabled, and
+// backslash-newlines removed. Offsets into `text` can be mapped back to the original bytes.
+#[derive(Debug, Clone)]
+pub struct Spliced {
+    pub text: String,
+    pieces: Vec<Piece>,
+    original_len: usize,
+}
+
+fn trigraph(c: u8) -> Option<char> {
+    Some(match c {
+        b'=' => '#',
+        b'(' => '[',
+        b')' => ']',
+        b'/' => '\\',
+        b'\'' => '^',
+        b'<' => '{',
+        b'>' => '}',
+        b'!' => '|',
+        b'-' => '~',
+        _ => return None,
+    })
+}
+
+fn newline_len(bytes: &[u8]) -> Option<usize> {
+    match bytes {
+        [b'\n', ..] => Some(1),
+        [b'\r', b'\n', ..] => Some(2),
+        _ => None,
+    }
+}
+
+pub fn splice(original: &str, trigraphs: bool) -> Spliced {
+    let bytes = original.as_bytes();
+    let mut spliced = Spliced { text: String::with_capacity(original.len()), pieces: Vec::new(), original_len: original.len() };
+    // start of the text not yet copied
+    let mut run = 0;
+    let mut position = 0;
+
+    while position < bytes.len() {
+        let replacement = match bytes[position..] {
+            [b'?', b'?', c, ..] if trigraphs => trigraph(c),
+            _ => None,
+        };
+
+        let backslash = match (bytes[position], replacement) {
+            (b'\\', _) => 1,
+            (_, Some('\\')) => 3,
+            _ => 0,
+        };
+
+        if backslash > 0 && let Some(newline) = newline_len(&bytes[position + backslash..]) {
+            spliced.copy(original, run, position);
+            position += backslash + newline;
+            run = position;
+            continue;
+        }
+
+        if let Some(replacement) = replacement {
+            spliced.copy(original, run, position);
+            spliced.pieces.push(Piece { spliced_start: spliced.text.len(), original_start: position, len: 1, trigraph: true });
+            spliced.text.push(replacement);
+            position += 3;
+            run = position;
+            continue;
+        }
+
+        position += 1;
+    }
+
+    spliced.copy(original, run, bytes.len());
+    spliced
+}
+
+impl Spliced {
+    fn copy(&mut self, original: &str, start: usize, end: usize) {
+        if start < end {
+            self.pieces.push(Piece { spliced_start: self.text.len(), original_start: start, len: end - start, trigraph: false });
+            self.text.push_str(&original[start..end]);
+        }
+    }
+
+    // Where a span starting at `offset` in the spliced text starts in the original.
+    pub fn original_start(&self, offset: usize) -> usize {
+        match self.pieces[..self.pieces.partition_point(|piece| piece.spliced_start <= offset)].last() {
+            Some(piece) if offset < piece.spliced_end() =>
+                piece.original_start + if piece.trigraph { 0 } else { offset - piece.spliced_start },
+            _ => self.original_len,
+        }
+    }
+
+    // Where a span ending at `offset` in the spliced text ends in the original.
+    pub fn original_end(&self, offset: usize) -> usize {
+        match self.pieces[..self.pieces.partition_point(|piece| piece.spliced_start < offset)].last() {
+            Some(piece) => piece.original_start + if piece.trigraph { 3 } else { offset - piece.spliced_start },
+            None => self.original_start(offset),
+        }
+    }
+
+    pub fn original_range(&self, range: Range<usize>) -> Range<usize> {
+        let start = self.original_start(range.start);
+
+        if range.is_empty() {
+            start..start
+        } else {
+            start..self.original_end(range.end)
+        }
+    }
+
+    // The spliced offset of an original one; offsets inside a removed backslash-newline
+    // move on to the text after it.
+    pub fn spliced_offset(&self, original: usize) -> usize {
+        let Some(piece) = self.pieces[..self.pieces.partition_point(|piece| piece.original_start <= original)].last() else {
+            return 0;
+        };
+
+        if original >= piece.original_start + piece.original_len() {
+            piece.spliced_end()
+        } else if piece.trigraph {
+            piece.spliced_start + (original > piece.original_start) as usize
+        } else {
+            piece.spliced_start + (original - piece.original_start)
+        }
+    }
+
+    // The parts of a spliced range with where each came from, and whether it maps byte for byte.
+    pub fn origins(&self, range: Range<usize>) -> impl Iterator<Item = (Range<usize>, Range<usize>, bool)> + '_ {
+        let first = self.pieces.partition_point(|piece| piece.spliced_end() <= range.start);
+
+        self.pieces[first..].iter()
+            .take_while(move |piece| piece.spliced_start < range.end)
+            .map(move |piece| {
+                let start = range.start.max(piece.spliced_start);
+                let end = range.end.min(piece.spliced_end());
+                let original = if piece.trigraph {
+                    piece.original_start..piece.original_start + 3
+                } else {
+                    piece.original_start + (start - piece.spliced_start)..piece.original_start + (end - piece.spliced_start)
+                };
+
+                (start..end, original, !piece.trigraph)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_splicing() {
+        let original = "#define A \\\n  1\\\r\nx\n";
+        let spliced = splice(original, false);
+
+        assert_eq!(spliced.text, "#define A   1x\n");
+
+        let one = spliced.text.find('1').unwrap();
+        assert_eq!(&original[spliced.original_range(one..one + 2)], "1\\\r\nx");
+        assert_eq!(spliced.original_start(one + 1), original.find('x').unwrap());
+        assert_eq!(spliced.spliced_offset(original.find('\r').unwrap()), one + 1);
+        assert_eq!(spliced.original_start(spliced.text.len()), original.len());
+    }
+
+    #[test]
+    fn trigraphs() {
+        let original = "??=define X ??( ??/\nx ??) ??";
+
+        assert_eq!(splice(original, false).text, original);
+
+        let spliced = splice(original, true);
+        assert_eq!(spliced.text, "#define X [ x ] ??");
+        assert_eq!(spliced.original_range(0..1), 0..3);
+        assert_eq!(spliced.original_range(10..11), 12..15);
+        assert_eq!(spliced.spliced_offset(13), 11);
+
+        let origins: Vec<_> = spliced.origins(0..3).collect();
+        assert_eq!(origins, vec![(0..1, 0..3, false), (1..3, 3..5, true)]);
+    }
+}
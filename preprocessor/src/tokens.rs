@@ -0,0 +1,368 @@
+use std::collections::BTreeSet;
+use std::rc::Rc;
+use common::identifiers::{is_identifier_continue, is_identifier_start, universal_character_name};
+use common::source_file::Id;
+use common::span::Span;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum PpKind {
+    Identifier,
+    Number,
+    CharConstant,
+    StringLiteral,
+    Punctuator,
+    Newline,
+    Other,
+    // Stands in for an empty macro argument next to `##`, removed after pasting.
+    Placemarker,
+    Eof,
+}
+
+pub type HideSet = Rc<BTreeSet<String>>;
+
+#[derive(Debug, Clone)]
+pub struct PpToken {
+    pub kind: PpKind,
+    pub text: String,
+    // where the token is spelled; for tokens from a macro body that is the definition
+    pub span: Span,
+    // bytes of whitespace and comments between the previous token and this one, in its file
+    pub leading: usize,
+    pub has_space: bool,
+    pub at_line_start: bool,
+    // macros that must not be expanded again inside this token's expansion
+    pub hideset: HideSet,
+    // use site of the outermost macro expansion this token came out of, carrying the macros
+    // it went through
+    pub expansion: Option<Span>,
+}
+
+impl PpToken {
+    pub fn new(kind: PpKind, text: impl Into<String>, span: Span) -> PpToken {
+        PpToken {
+            kind,
+            text: text.into(),
+            span,
+            leading: 0,
+            has_space: false,
+            at_line_start: false,
+            hideset: HideSet::default(),
+            expansion: None,
+        }
+    }
+
+    pub fn is(&self, punctuator: &str) -> bool {
+        self.kind == PpKind::Punctuator && self.text == punctuator
+    }
+
+    // `%:` and `%:%:` are the digraph spellings of `#` and `##`.
+    pub fn is_hash(&self) -> bool {
+        self.is("#") || self.is("%:")
+    }
+
+    pub fn is_hash_hash(&self) -> bool {
+        self.is("##") || self.is("%:%:")
+    }
+}
+
+// longest first, so the first match is the maximal munch
+const PUNCTUATORS: &[&str] = &[
+    "%:%:", "...", "<<=", ">>=",
+    "->", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "*=", "/=", "%=", "+=", "-=",
+    "&=", "^=", "|=", "##", "<:", ":>", "<%", "%>", "%:",
+    "[", "]", "(", ")", "{", "}", ".", "&", "*", "+", "-", "~", "!", "/", "%", "<", ">", "^", "|",
+    "?", ":", ";", "=", ",", "#",
+];
+
+struct Tokenizer<'a> {
+    text: &'a str,
+    bytes: &'a [u8],
+    unit: &'a Id,
+    position: usize,
+    nested_comments: bool,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn peek(&self, offset: usize) -> Option<u8> {
+        self.bytes.get(self.position + offset).copied()
+    }
+
+    // Skips whitespace and comments (but not newlines), returning whether anything was skipped.
+    fn skip_space(&mut self) -> bool {
+        let start = self.position;
+
+        while let Some(c) = self.peek(0) {
+            match c {
+                b' ' | b'\t' | b'\r' | b'\x0b' | b'\x0c' => self.position += 1,
+                b'/' if self.peek(1) == Some(b'/') => {
+                    while self.peek(0).is_some_and(|c| c != b'\n') {
+                        self.position += 1;
+                    }
+                }
+                b'/' if self.peek(1) == Some(b'*') => self.block_comment(),
+                _ => break,
+            }
+        }
+
+        self.position > start
+    }
+
+    // the lexer reports unterminated and nested comments, this only needs to skip them
+    fn block_comment(&mut self) {
+        let mut depth = 0;
+
+        while self.position + 1 < self.bytes.len() {
+            match (self.bytes[self.position], self.bytes[self.position + 1]) {
+                (b'/', b'*') if depth == 0 || self.nested_comments => {
+                    depth += 1;
+                    self.position += 2;
+                }
+                (b'*', b'/') => {
+                    depth -= 1;
+                    self.position += 2;
+
+                    if depth == 0 {
+                        return;
+                    }
+                }
+                _ => self.position += 1,
+            }
+        }
+
+        self.position = self.bytes.len();
+    }
+
+    fn quoted(&mut self, quote: u8) -> bool {
+        let mut position = self.position + 1;
+
+        while let Some(&c) = self.bytes.get(position) {
+            match c {
+                b'\\' => position += 2,
+                b'\n' => return false,
+                _ if c == quote => {
+                    self.position = position + 1;
+                    return true;
+                }
+                _ => position += 1,
+            }
+        }
+
+        false
+    }
+
+    fn number(&mut self) {
+        self.position += 1;
+
+        while let Some(c) = self.peek(0) {
+            if matches!(c, b'e' | b'E' | b'p' | b'P') && matches!(self.peek(1), Some(b'+' | b'-')) {
+                self.position += 2;
+            } else if is_identifier_continue(c) || c == b'.' {
+                self.position += 1;
+            } else if c == b'\'' && self.peek(1).is_some_and(is_identifier_continue) {
+                // C23 digit separator
+                self.position += 2;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn next_kind(&mut self) -> PpKind {
+        let c = self.bytes[self.position];
+
+        if c == b'\n' {
+            self.position += 1;
+            return PpKind::Newline;
+        }
+
+        if is_identifier_start(c) || universal_character_name(&self.bytes[self.position..]).is_some() {
+            let start = self.position;
+
+            loop {
+                if self.peek(0).is_some_and(is_identifier_continue) {
+                    self.position += 1;
+                } else if let Some(len) = universal_character_name(&self.bytes[self.position..]) {
+                    self.position += len;
+                } else {
+                    break;
+                }
+            }
+
+            let prefix = &self.text[start..self.position];
+
+            if matches!(prefix, "L" | "u" | "U" | "u8") && let Some(quote @ (b'"' | b'\'')) = self.peek(0) {
+                let end_of_prefix = self.position;
+
+                if self.quoted(quote) {
+                    return if quote == b'"' { PpKind::StringLiteral } else { PpKind::CharConstant };
+                }
+
+                self.position = end_of_prefix;
+            }
+
+            return PpKind::Identifier;
+        }
+
+        if c.is_ascii_digit() || (c == b'.' && self.peek(1).is_some_and(|c| c.is_ascii_digit())) {
+            self.number();
+            return PpKind::Number;
+        }
+
+        if c == b'"' || c == b'\'' {
+            if self.quoted(c) {
+                return if c == b'"' { PpKind::StringLiteral } else { PpKind::CharConstant };
+            }
+
+            // an unmatched quote is a token of its own
+            self.position += 1;
+            return PpKind::Other;
+        }
+
+        let rest = &self.text[self.position..];
+
+        if let Some(punctuator) = PUNCTUATORS.iter().find(|p| rest.starts_with(*p)) {
+            self.position += punctuator.len();
+            return PpKind::Punctuator;
+        }
+
+        self.position += rest.chars().next().map_or(1, char::len_utf8);
+        PpKind::Other
+    }
+}
+
+// Splits `text` into preprocessing tokens, ending with an `Eof` token that carries any
+// trailing whitespace. Comments count as whitespace.
+pub fn tokenize(text: &str, unit: &Id) -> Vec<PpToken> {
+    tokenize_with(text, unit, false)
+}
+
+// Same as `tokenize`, with block comments nesting if `nested_comments` is set.
+pub fn tokenize_with(text: &str, unit: &Id, nested_comments: bool) -> Vec<PpToken> {
+    let mut tokenizer = Tokenizer { text, bytes: text.as_bytes(), unit, position: 0, nested_comments };
+    let mut tokens = Vec::new();
+    let mut at_line_start = true;
+
+    loop {
+        let gap_start = tokenizer.position;
+        let has_space = tokenizer.skip_space();
+        let start = tokenizer.position;
+
+        let kind = if start == text.len() {
+            PpKind::Eof
+        } else {
+            tokenizer.next_kind()
+        };
+
+        let end = tokenizer.position;
+
+        tokens.push(PpToken {
+            leading: start - gap_start,
+            has_space,
+            at_line_start,
+            ..PpToken::new(kind, &text[start..end], Span::new_with_unit(start, end - start, tokenizer.unit.clone()))
+        });
+
+        if kind == PpKind::Eof {
+            break;
+        }
+
+        at_line_start = kind == PpKind::Newline;
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(text: &str) -> Vec<(PpKind, String)> {
+        tokenize(text, &Id::Main).into_iter().map(|t| (t.kind, t.text)).collect()
+    }
+
+    #[test]
+    fn directive_line() {
+        let tokens = tokenize("  # define X 1\nX", &Id::Main);
+
+        assert_eq!(tokens[0].text, "#");
+        assert!(tokens[0].at_line_start);
+        assert_eq!(tokens[0].leading, 2);
+        assert_eq!(tokens[1].text, "define");
+        assert!(tokens[1].has_space);
+        assert_eq!(tokens[4].kind, PpKind::Newline);
+        assert_eq!(tokens[5].text, "X");
+        assert!(tokens[5].at_line_start);
+        assert_eq!(tokens[6].kind, PpKind::Eof);
+    }
+
+    #[test]
+    fn maximal_munch() {
+        assert_eq!(kinds("a<<=b...##"), vec![
+            (PpKind::Identifier, "a".to_string()),
+            (PpKind::Punctuator, "<<=".to_string()),
+            (PpKind::Identifier, "b".to_string()),
+            (PpKind::Punctuator, "...".to_string()),
+            (PpKind::Punctuator, "##".to_string()),
+            (PpKind::Eof, "".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn numbers_and_literals() {
+        assert_eq!(kinds("0x1Fu 1e+5 1'000 .5 L'a' \"a\\\"b\" u8\"x\""), vec![
+            (PpKind::Number, "0x1Fu".to_string()),
+            (PpKind::Number, "1e+5".to_string()),
+            (PpKind::Number, "1'000".to_string()),
+            (PpKind::Number, ".5".to_string()),
+            (PpKind::CharConstant, "L'a'".to_string()),
+            (PpKind::StringLiteral, "\"a\\\"b\"".to_string()),
+            (PpKind::StringLiteral, "u8\"x\"".to_string()),
+            (PpKind::Eof, "".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn comments_are_whitespace() {
+        let tokens = tokenize("a/* x\ny */b // c\nd", &Id::Main);
+
+        assert_eq!(tokens[1].text, "b");
+        assert!(tokens[1].has_space);
+        assert!(!tokens[1].at_line_start);
+        assert_eq!(tokens[2].kind, PpKind::Newline);
+        assert_eq!(tokens[2].leading, 5);
+    }
+
+    #[test]
+    fn nested_comments() {
+        assert_eq!(kinds("/* a /* b */ c */"), vec![
+            (PpKind::Identifier, "c".to_string()),
+            (PpKind::Punctuator, "*".to_string()),
+            (PpKind::Punctuator, "/".to_string()),
+            (PpKind::Eof, "".to_string()),
+        ]);
+
+        let tokens = tokenize_with("/* a /* b */ c */ d", &Id::Main, true);
+        assert_eq!(tokens[0].text, "d");
+    }
+
+    #[test]
+    fn universal_character_names() {
+        assert_eq!(kinds("caf\\u00e9 \\U0001F600x \\u0041"), vec![
+            (PpKind::Identifier, "caf\\u00e9".to_string()),
+            (PpKind::Identifier, "\\U0001F600x".to_string()),
+            (PpKind::Other, "\\".to_string()),
+            (PpKind::Identifier, "u0041".to_string()),
+            (PpKind::Eof, "".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn unterminated_quote_and_unicode() {
+        assert_eq!(kinds("'a é"), vec![
+            (PpKind::Other, "'".to_string()),
+            (PpKind::Identifier, "a".to_string()),
+            (PpKind::Other, "é".to_string()),
+            (PpKind::Eof, "".to_string()),
+        ]);
+    }
+}